@@ -0,0 +1,24 @@
+//Prints chat from the channels given on the command line without storing anything, eg -
+//cargo run --example print_chat -- channel_one channel_two
+use futures::StreamExt;
+use twitch_chat_parser::{messages, Channels, Collector};
+
+#[tokio::main]
+async fn main() {
+    let channels: Vec<String> = std::env::args().skip(1).collect();
+    let (builder, records) = Collector::builder()
+        .channels(Channels::List(channels))
+        .channel(1024);
+
+    tokio::spawn(async move {
+        let mut messages = Box::pin(messages(records));
+        while let Some(m) = messages.next().await {
+            println!("{} {}: {}", m.channel, m.tags.display_name, m.message);
+        }
+    });
+
+    if let Err(e) = builder.build().run().await {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
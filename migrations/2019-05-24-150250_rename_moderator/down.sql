@@ -1 +1 @@
-ALTER TABLE messages RENAME TABLE mod TO moderator;
+ALTER TABLE messages RENAME COLUMN mod TO moderator;
@@ -0,0 +1,8 @@
+CREATE TABLE outages (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	started_at TEXT NOT NULL,
+	ended_at TEXT NOT NULL,
+	reason TEXT NOT NULL
+);
+CREATE INDEX outagechannelindex ON outages(channel);
@@ -0,0 +1,4 @@
+DROP TABLE room_states;
+DROP TABLE clear_msgs;
+DROP TABLE clear_chats;
+DROP TABLE user_notices;
@@ -0,0 +1,52 @@
+CREATE TABLE user_notices (
+	id TEXT PRIMARY KEY NOT NULL,
+	channel TEXT NOT NULL,
+	room_id INTEGER NOT NULL,
+	msg_id TEXT NOT NULL,
+	user_id TEXT,
+	login TEXT,
+	display_name TEXT,
+	system_msg TEXT,
+	message TEXT,
+	params TEXT NOT NULL,
+	tmi_sent_ts TEXT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX usernoticechannelindex ON user_notices(channel);
+
+CREATE TABLE clear_chats (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	room_id INTEGER NOT NULL,
+	target_login TEXT,
+	target_user_id TEXT,
+	ban_duration INTEGER,
+	tmi_sent_ts TEXT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX clearchatchannelindex ON clear_chats(channel);
+
+CREATE TABLE clear_msgs (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	login TEXT,
+	target_msg_id TEXT NOT NULL,
+	message TEXT,
+	tmi_sent_ts TEXT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX clearmsgchannelindex ON clear_msgs(channel);
+
+CREATE TABLE room_states (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	room_id INTEGER NOT NULL,
+	emote_only BOOLEAN,
+	followers_only INTEGER,
+	r9k BOOLEAN,
+	slow INTEGER,
+	subs_only BOOLEAN,
+	received_at TEXT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX roomstatechannelindex ON room_states(channel);
@@ -0,0 +1,8 @@
+CREATE TABLE presence (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	login TEXT NOT NULL,
+	event TEXT NOT NULL,
+	received_at TEXT NOT NULL
+);
+CREATE INDEX presencechannelindex ON presence(channel, received_at);
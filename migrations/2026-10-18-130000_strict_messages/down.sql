@@ -0,0 +1,31 @@
+ALTER TABLE messages RENAME TO messages_strict;
+DROP INDEX channelindex;
+
+CREATE TABLE messages (
+	id TEXT PRIMARY KEY,
+	badge_info TEXT,
+	badges TEXT,
+	bits INTEGER,
+	color TEXT,
+	display_name TEXT,
+	emotes TEXT,
+	message_id TEXT,
+	mod BOOLEAN,
+	room_id INTEGER,
+	tmi_sent_ts DATETIME,
+	user_id TEXT,
+	channel TEXT,
+	message TEXT,
+	raw_message TEXT
+);
+CREATE INDEX channelindex ON messages(channel);
+
+INSERT INTO messages
+SELECT id, badge_info, badges, bits, color, display_name, emotes, NULL, mod, room_id,
+	strftime('%Y-%m-%dT%H:%M:%f', tmi_sent_ts) || '+00:00',
+	user_id, channel, message, raw_message
+FROM messages_strict;
+INSERT INTO messages SELECT * FROM messages_unmigrated;
+
+DROP TABLE messages_strict;
+DROP TABLE messages_unmigrated;
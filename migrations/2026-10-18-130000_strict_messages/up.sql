@@ -0,0 +1,39 @@
+-- sqlite can't change column constraints so the table is rebuilt. Timestamps were stored as
+-- rfc3339 strings, they're rewritten in the format diesel uses for Timestamp columns. Rows that
+-- are missing a value that's now required are kept in messages_unmigrated rather than dropped.
+ALTER TABLE messages RENAME TO messages_old;
+DROP INDEX channelindex;
+
+CREATE TABLE messages (
+	id TEXT PRIMARY KEY NOT NULL,
+	badge_info TEXT,
+	badges TEXT,
+	bits INTEGER,
+	color TEXT,
+	display_name TEXT NOT NULL,
+	emotes TEXT,
+	mod BOOLEAN,
+	room_id INTEGER NOT NULL,
+	tmi_sent_ts TIMESTAMP NOT NULL,
+	user_id TEXT NOT NULL,
+	channel TEXT NOT NULL,
+	message TEXT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX channelindex ON messages(channel, tmi_sent_ts);
+
+INSERT INTO messages
+SELECT id, badge_info, badges, bits, color, display_name, emotes, mod, room_id,
+	CASE WHEN strftime('%f', tmi_sent_ts) LIKE '%.000'
+		THEN strftime('%Y-%m-%d %H:%M:%S', tmi_sent_ts)
+		ELSE strftime('%Y-%m-%d %H:%M:%f', tmi_sent_ts)
+	END,
+	user_id, channel, message, raw_message
+FROM messages_old
+WHERE id IS NOT NULL AND display_name IS NOT NULL AND room_id IS NOT NULL
+	AND strftime('%s', tmi_sent_ts) IS NOT NULL AND user_id IS NOT NULL AND channel IS NOT NULL
+	AND message IS NOT NULL AND raw_message IS NOT NULL;
+
+CREATE TABLE messages_unmigrated AS
+SELECT * FROM messages_old WHERE id IS NULL OR id NOT IN (SELECT id FROM messages);
+DROP TABLE messages_old;
@@ -0,0 +1,98 @@
+CREATE TABLE messages_text_ids (
+	id TEXT PRIMARY KEY NOT NULL,
+	badge_info TEXT,
+	badges TEXT,
+	bits INTEGER,
+	color TEXT,
+	display_name TEXT NOT NULL,
+	emotes TEXT,
+	mod BOOLEAN,
+	room_id INTEGER NOT NULL,
+	tmi_sent_ts TIMESTAMP NOT NULL,
+	user_id TEXT NOT NULL,
+	channel TEXT NOT NULL,
+	message TEXT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+
+INSERT INTO messages_text_ids
+SELECT lower(substr(hex(id), 1, 8) || '-' || substr(hex(id), 9, 4) || '-'
+		|| substr(hex(id), 13, 4) || '-' || substr(hex(id), 17, 4) || '-' || substr(hex(id), 21)),
+	badge_info, badges, bits, color, display_name, emotes, mod, room_id,
+	CASE WHEN tmi_sent_ts % 1000 = 0
+		THEN strftime('%Y-%m-%d %H:%M:%S', tmi_sent_ts / 1000, 'unixepoch')
+		ELSE strftime('%Y-%m-%d %H:%M:%S', tmi_sent_ts / 1000, 'unixepoch')
+			|| printf('.%03d', tmi_sent_ts % 1000)
+	END,
+	user_id, channel, message, raw_message
+FROM messages;
+
+DROP INDEX channelindex;
+DROP TABLE messages;
+ALTER TABLE messages_text_ids RENAME TO messages;
+CREATE INDEX channelindex ON messages(channel, tmi_sent_ts);
+
+ALTER TABLE user_notices RENAME TO user_notices_blob_ids;
+DROP INDEX usernoticechannelindex;
+CREATE TABLE user_notices (
+	id TEXT PRIMARY KEY NOT NULL,
+	channel TEXT NOT NULL,
+	room_id INTEGER NOT NULL,
+	msg_id TEXT NOT NULL,
+	user_id TEXT,
+	login TEXT,
+	display_name TEXT,
+	system_msg TEXT,
+	message TEXT,
+	params TEXT NOT NULL,
+	tmi_sent_ts TEXT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX usernoticechannelindex ON user_notices(channel);
+INSERT INTO user_notices
+SELECT lower(substr(hex(id), 1, 8) || '-' || substr(hex(id), 9, 4) || '-'
+		|| substr(hex(id), 13, 4) || '-' || substr(hex(id), 17, 4) || '-' || substr(hex(id), 21)),
+	channel, room_id, msg_id, user_id, login, display_name, system_msg, message, params,
+	strftime('%Y-%m-%dT%H:%M:%f+00:00', tmi_sent_ts / 1000.0, 'unixepoch'), raw_message
+FROM user_notices_blob_ids;
+DROP TABLE user_notices_blob_ids;
+
+ALTER TABLE clear_chats RENAME TO clear_chats_millis;
+DROP INDEX clearchatchannelindex;
+CREATE TABLE clear_chats (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	room_id INTEGER NOT NULL,
+	target_login TEXT,
+	target_user_id TEXT,
+	ban_duration INTEGER,
+	tmi_sent_ts TEXT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX clearchatchannelindex ON clear_chats(channel);
+INSERT INTO clear_chats
+SELECT id, channel, room_id, target_login, target_user_id, ban_duration,
+	strftime('%Y-%m-%dT%H:%M:%f+00:00', tmi_sent_ts / 1000.0, 'unixepoch'), raw_message
+FROM clear_chats_millis;
+DROP TABLE clear_chats_millis;
+
+ALTER TABLE clear_msgs RENAME TO clear_msgs_blob_ids;
+DROP INDEX clearmsgchannelindex;
+CREATE TABLE clear_msgs (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	login TEXT,
+	target_msg_id TEXT NOT NULL,
+	message TEXT,
+	tmi_sent_ts TEXT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX clearmsgchannelindex ON clear_msgs(channel);
+INSERT INTO clear_msgs
+SELECT id, channel, login,
+	lower(substr(hex(target_msg_id), 1, 8) || '-' || substr(hex(target_msg_id), 9, 4) || '-'
+		|| substr(hex(target_msg_id), 13, 4) || '-' || substr(hex(target_msg_id), 17, 4) || '-'
+		|| substr(hex(target_msg_id), 21)),
+	message, strftime('%Y-%m-%dT%H:%M:%f+00:00', tmi_sent_ts / 1000.0, 'unixepoch'), raw_message
+FROM clear_msgs_blob_ids;
+DROP TABLE clear_msgs_blob_ids;
@@ -0,0 +1,140 @@
+-- ids are stored as their 16 bytes and timestamps as milliseconds since the epoch. sqlite can't
+-- turn hex into a blob before 3.41 (unhex), so each pair of hex digits is looked up in hex_bytes.
+-- Messages that can't be converted are kept in messages_unmigrated.
+CREATE TEMP TABLE hex_bytes (
+	pair TEXT PRIMARY KEY NOT NULL,
+	byte BLOB NOT NULL
+);
+INSERT INTO hex_bytes
+WITH RECURSIVE n(i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM n WHERE i < 255)
+SELECT printf('%02x', i), substr(
+	X'000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f404142434445464748494a4b4c4d4e4f505152535455565758595a5b5c5d5e5f606162636465666768696a6b6c6d6e6f707172737475767778797a7b7c7d7e7f808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9fa0a1a2a3a4a5a6a7a8a9aaabacadaeafb0b1b2b3b4b5b6b7b8b9babbbcbdbebfc0c1c2c3c4c5c6c7c8c9cacbcccdcecfd0d1d2d3d4d5d6d7d8d9dadbdcdddedfe0e1e2e3e4e5e6e7e8e9eaebecedeeeff0f1f2f3f4f5f6f7f8f9fafbfcfdfeff',
+	i + 1, 1)
+FROM n;
+
+-- every text id, with its bytes or NULL if it isn't a uuid
+CREATE TEMP TABLE text_ids (
+	text_id TEXT PRIMARY KEY NOT NULL,
+	id BLOB
+);
+INSERT OR IGNORE INTO text_ids
+WITH RECURSIVE ids(text_id, rest, id) AS (
+	SELECT id, lower(replace(id, '-', '')), X'' FROM messages
+	UNION ALL
+	SELECT id, lower(replace(id, '-', '')), X'' FROM user_notices
+	UNION ALL
+	SELECT target_msg_id, lower(replace(target_msg_id, '-', '')), X'' FROM clear_msgs
+	UNION ALL
+	SELECT text_id, substr(rest, 3), id || (SELECT byte FROM hex_bytes WHERE pair = substr(rest, 1, 2))
+	FROM ids WHERE rest != '' AND id IS NOT NULL
+)
+SELECT text_id, CASE WHEN rest = '' AND length(text_id) = 36 THEN CAST(id AS BLOB) END
+FROM ids WHERE rest = '' OR id IS NULL;
+
+-- julianday counts days from noon 4714 BC, and 1970-01-01 is day 2440587.5
+ALTER TABLE messages RENAME TO messages_text_ids;
+DROP INDEX channelindex;
+
+CREATE TABLE messages (
+	id BLOB PRIMARY KEY NOT NULL,
+	badge_info TEXT,
+	badges TEXT,
+	bits INTEGER,
+	color TEXT,
+	display_name TEXT NOT NULL,
+	emotes TEXT,
+	mod BOOLEAN,
+	room_id INTEGER NOT NULL,
+	tmi_sent_ts BIGINT NOT NULL,
+	user_id TEXT NOT NULL,
+	channel TEXT NOT NULL,
+	message TEXT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX channelindex ON messages(channel, tmi_sent_ts);
+
+INSERT INTO messages
+SELECT t.id, m.badge_info, m.badges, m.bits, m.color, m.display_name, m.emotes, m.mod, m.room_id,
+	CAST(round((julianday(m.tmi_sent_ts) - 2440587.5) * 86400000) AS INTEGER),
+	m.user_id, m.channel, m.message, m.raw_message
+FROM messages_text_ids m JOIN text_ids t ON t.text_id = m.id
+WHERE t.id IS NOT NULL AND julianday(m.tmi_sent_ts) IS NOT NULL;
+
+INSERT INTO messages_unmigrated
+SELECT m.id, m.badge_info, m.badges, m.bits, m.color, m.display_name, m.emotes, NULL, m.mod,
+	m.room_id, m.tmi_sent_ts, m.user_id, m.channel, m.message, m.raw_message
+FROM messages_text_ids m JOIN text_ids t ON t.text_id = m.id
+WHERE t.id IS NULL OR julianday(m.tmi_sent_ts) IS NULL;
+DROP TABLE messages_text_ids;
+
+-- notices and clears are always written from parsed ids and timestamps so all of them convert
+ALTER TABLE user_notices RENAME TO user_notices_text_ids;
+DROP INDEX usernoticechannelindex;
+
+CREATE TABLE user_notices (
+	id BLOB PRIMARY KEY NOT NULL,
+	channel TEXT NOT NULL,
+	room_id INTEGER NOT NULL,
+	msg_id TEXT NOT NULL,
+	user_id TEXT,
+	login TEXT,
+	display_name TEXT,
+	system_msg TEXT,
+	message TEXT,
+	params TEXT NOT NULL,
+	tmi_sent_ts BIGINT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX usernoticechannelindex ON user_notices(channel);
+
+INSERT INTO user_notices
+SELECT (SELECT t.id FROM text_ids t WHERE t.text_id = n.id), n.channel, n.room_id, n.msg_id,
+	n.user_id, n.login, n.display_name, n.system_msg, n.message, n.params,
+	CAST(round((julianday(n.tmi_sent_ts) - 2440587.5) * 86400000) AS INTEGER), n.raw_message
+FROM user_notices_text_ids n;
+DROP TABLE user_notices_text_ids;
+
+ALTER TABLE clear_chats RENAME TO clear_chats_text_ts;
+DROP INDEX clearchatchannelindex;
+
+CREATE TABLE clear_chats (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	room_id INTEGER NOT NULL,
+	target_login TEXT,
+	target_user_id TEXT,
+	ban_duration INTEGER,
+	tmi_sent_ts BIGINT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX clearchatchannelindex ON clear_chats(channel);
+
+INSERT INTO clear_chats
+SELECT id, channel, room_id, target_login, target_user_id, ban_duration,
+	CAST(round((julianday(tmi_sent_ts) - 2440587.5) * 86400000) AS INTEGER), raw_message
+FROM clear_chats_text_ts;
+DROP TABLE clear_chats_text_ts;
+
+ALTER TABLE clear_msgs RENAME TO clear_msgs_text_ids;
+DROP INDEX clearmsgchannelindex;
+
+CREATE TABLE clear_msgs (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	login TEXT,
+	target_msg_id BLOB NOT NULL,
+	message TEXT,
+	tmi_sent_ts BIGINT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX clearmsgchannelindex ON clear_msgs(channel);
+
+INSERT INTO clear_msgs
+SELECT c.id, c.channel, c.login, (SELECT t.id FROM text_ids t WHERE t.text_id = c.target_msg_id),
+	c.message, CAST(round((julianday(c.tmi_sent_ts) - 2440587.5) * 86400000) AS INTEGER),
+	c.raw_message
+FROM clear_msgs_text_ids c;
+DROP TABLE clear_msgs_text_ids;
+
+DROP TABLE text_ids;
+DROP TABLE hex_bytes;
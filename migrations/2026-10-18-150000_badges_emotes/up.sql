@@ -0,0 +1,38 @@
+CREATE TABLE message_badges (
+	message_id BLOB NOT NULL,
+	name TEXT NOT NULL,
+	version TEXT NOT NULL,
+	PRIMARY KEY (message_id, name)
+);
+CREATE INDEX messagebadgeindex ON message_badges(name, version);
+
+CREATE TABLE message_emotes (
+	message_id BLOB NOT NULL,
+	emote_id TEXT NOT NULL,
+	start_index INTEGER NOT NULL,
+	end_index INTEGER NOT NULL,
+	PRIMARY KEY (message_id, start_index)
+);
+CREATE INDEX messageemoteindex ON message_emotes(emote_id);
+
+-- fill in from the badges and emotes json of existing messages, eg - ["subscriber/6"] and
+-- ["25:0-4,12-16"]
+INSERT OR IGNORE INTO message_badges
+SELECT m.id, substr(b.value, 1, instr(b.value, '/') - 1), substr(b.value, instr(b.value, '/') + 1)
+FROM messages m, json_each(m.badges) b
+WHERE m.badges IS NOT NULL AND instr(b.value, '/') > 0;
+
+INSERT OR IGNORE INTO message_emotes
+WITH RECURSIVE ranges(message_id, emote_id, range, rest) AS (
+	SELECT m.id, substr(e.value, 1, instr(e.value, ':') - 1), NULL,
+		substr(e.value, instr(e.value, ':') + 1) || ','
+	FROM messages m, json_each(m.emotes) e
+	WHERE m.emotes IS NOT NULL AND instr(e.value, ':') > 0
+	UNION ALL
+	SELECT message_id, emote_id, substr(rest, 1, instr(rest, ',') - 1),
+		substr(rest, instr(rest, ',') + 1)
+	FROM ranges WHERE rest != ''
+)
+SELECT message_id, emote_id, CAST(substr(range, 1, instr(range, '-') - 1) AS INTEGER),
+	CAST(substr(range, instr(range, '-') + 1) AS INTEGER)
+FROM ranges WHERE instr(range, '-') > 0;
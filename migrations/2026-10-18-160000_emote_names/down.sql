@@ -0,0 +1,2 @@
+DROP INDEX messageemotenameindex;
+ALTER TABLE message_emotes DROP COLUMN name;
@@ -0,0 +1,8 @@
+-- substr counts characters, which is how twitch gives emote positions
+ALTER TABLE message_emotes ADD COLUMN name TEXT NOT NULL DEFAULT '';
+UPDATE message_emotes SET name = COALESCE((
+	SELECT substr(m.message, message_emotes.start_index + 1,
+		message_emotes.end_index - message_emotes.start_index + 1)
+	FROM messages m WHERE m.id = message_emotes.message_id
+), '');
+CREATE INDEX messageemotenameindex ON message_emotes(name);
@@ -0,0 +1,10 @@
+-- founders count their months the same way as subscribers
+ALTER TABLE messages ADD COLUMN subscription_months INTEGER;
+UPDATE messages SET subscription_months = CAST(substr(
+	substr(badge_info, instr(badge_info, 'subscriber/') + length('subscriber/')),
+	1, instr(substr(badge_info, instr(badge_info, 'subscriber/') + length('subscriber/')) || ',', ',') - 1
+) AS INTEGER) WHERE instr(badge_info, 'subscriber/') > 0;
+UPDATE messages SET subscription_months = CAST(substr(
+	substr(badge_info, instr(badge_info, 'founder/') + length('founder/')),
+	1, instr(substr(badge_info, instr(badge_info, 'founder/') + length('founder/')) || ',', ',') - 1
+) AS INTEGER) WHERE instr(badge_info, 'founder/') > 0;
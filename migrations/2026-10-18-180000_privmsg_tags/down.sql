@@ -0,0 +1,11 @@
+DROP TABLE message_replies;
+ALTER TABLE messages DROP COLUMN other_tags;
+ALTER TABLE messages DROP COLUMN vip;
+ALTER TABLE messages DROP COLUMN user_type;
+ALTER TABLE messages DROP COLUMN turbo;
+ALTER TABLE messages DROP COLUMN subscriber;
+ALTER TABLE messages DROP COLUMN returning_chatter;
+ALTER TABLE messages DROP COLUMN flags;
+ALTER TABLE messages DROP COLUMN first_msg;
+ALTER TABLE messages DROP COLUMN emote_only;
+ALTER TABLE messages DROP COLUMN client_nonce;
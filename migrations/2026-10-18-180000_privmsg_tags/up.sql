@@ -0,0 +1,25 @@
+-- messages from before this have these as NULL. other_tags is a json object of any tags without
+-- a column of their own.
+ALTER TABLE messages ADD COLUMN client_nonce TEXT;
+ALTER TABLE messages ADD COLUMN emote_only BOOLEAN;
+ALTER TABLE messages ADD COLUMN first_msg BOOLEAN;
+ALTER TABLE messages ADD COLUMN flags TEXT;
+ALTER TABLE messages ADD COLUMN returning_chatter BOOLEAN;
+ALTER TABLE messages ADD COLUMN subscriber BOOLEAN;
+ALTER TABLE messages ADD COLUMN turbo BOOLEAN;
+ALTER TABLE messages ADD COLUMN user_type TEXT;
+ALTER TABLE messages ADD COLUMN vip BOOLEAN;
+ALTER TABLE messages ADD COLUMN other_tags TEXT;
+
+CREATE TABLE message_replies (
+	message_id BLOB PRIMARY KEY NOT NULL,
+	parent_msg_id BLOB NOT NULL,
+	parent_user_id TEXT,
+	parent_user_login TEXT,
+	parent_display_name TEXT,
+	parent_msg_body TEXT,
+	thread_parent_msg_id BLOB,
+	thread_parent_user_login TEXT
+);
+CREATE INDEX messagereplyparentindex ON message_replies(parent_msg_id);
+CREATE INDEX messagereplythreadindex ON message_replies(thread_parent_msg_id);
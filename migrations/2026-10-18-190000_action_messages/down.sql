@@ -0,0 +1,2 @@
+UPDATE messages SET message = char(1) || 'ACTION ' || message || char(1) WHERE action;
+ALTER TABLE messages DROP COLUMN action;
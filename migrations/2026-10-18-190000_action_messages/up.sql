@@ -0,0 +1,12 @@
+-- /me messages were stored with their \x01ACTION ... \x01 wrapper. Twitch counts emote positions
+-- from the text inside, so the names of their emotes are taken again once it's removed.
+ALTER TABLE messages ADD COLUMN action BOOLEAN NOT NULL DEFAULT 0;
+UPDATE messages SET action = 1,
+	message = substr(message, 9, length(message) - 8 - (substr(message, -1) = char(1)))
+WHERE substr(message, 1, 8) = char(1) || 'ACTION ';
+UPDATE message_emotes SET name = COALESCE((
+	SELECT substr(m.message, message_emotes.start_index + 1,
+		message_emotes.end_index - message_emotes.start_index + 1)
+	FROM messages m WHERE m.id = message_emotes.message_id
+), '')
+WHERE message_id IN (SELECT id FROM messages WHERE action);
@@ -0,0 +1,15 @@
+ALTER TABLE outages RENAME TO outages_millis;
+DROP INDEX outagechannelindex;
+CREATE TABLE outages (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	started_at TEXT NOT NULL,
+	ended_at TEXT NOT NULL,
+	reason TEXT NOT NULL
+);
+CREATE INDEX outagechannelindex ON outages(channel);
+INSERT INTO outages
+SELECT id, channel, strftime('%Y-%m-%dT%H:%M:%f+00:00', started_at / 1000.0, 'unixepoch'),
+	strftime('%Y-%m-%dT%H:%M:%f+00:00', ended_at / 1000.0, 'unixepoch'), reason
+FROM outages_millis;
+DROP TABLE outages_millis;
@@ -0,0 +1,17 @@
+-- outages are stored in milliseconds since the epoch like tmi_sent_ts
+ALTER TABLE outages RENAME TO outages_text_ts;
+DROP INDEX outagechannelindex;
+CREATE TABLE outages (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	started_at BIGINT NOT NULL,
+	ended_at BIGINT NOT NULL,
+	reason TEXT NOT NULL
+);
+CREATE INDEX outagechannelindex ON outages(channel);
+INSERT INTO outages
+SELECT id, channel,
+	CAST(round((julianday(started_at) - 2440587.5) * 86400000) AS INTEGER),
+	CAST(round((julianday(ended_at) - 2440587.5) * 86400000) AS INTEGER), reason
+FROM outages_text_ts;
+DROP TABLE outages_text_ts;
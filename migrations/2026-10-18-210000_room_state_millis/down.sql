@@ -0,0 +1,20 @@
+ALTER TABLE room_states RENAME TO room_states_millis;
+DROP INDEX roomstatechannelindex;
+CREATE TABLE room_states (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	room_id INTEGER NOT NULL,
+	emote_only BOOLEAN,
+	followers_only INTEGER,
+	r9k BOOLEAN,
+	slow INTEGER,
+	subs_only BOOLEAN,
+	received_at TEXT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX roomstatechannelindex ON room_states(channel);
+INSERT INTO room_states
+SELECT id, channel, room_id, emote_only, followers_only, r9k, slow, subs_only,
+	strftime('%Y-%m-%dT%H:%M:%f+00:00', received_at / 1000.0, 'unixepoch'), raw_message
+FROM room_states_millis;
+DROP TABLE room_states_millis;
@@ -0,0 +1,21 @@
+-- room states are stored in milliseconds since the epoch like tmi_sent_ts
+ALTER TABLE room_states RENAME TO room_states_text_ts;
+DROP INDEX roomstatechannelindex;
+CREATE TABLE room_states (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	room_id INTEGER NOT NULL,
+	emote_only BOOLEAN,
+	followers_only INTEGER,
+	r9k BOOLEAN,
+	slow INTEGER,
+	subs_only BOOLEAN,
+	received_at BIGINT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX roomstatechannelindex ON room_states(channel);
+INSERT INTO room_states
+SELECT id, channel, room_id, emote_only, followers_only, r9k, slow, subs_only,
+	CAST(round((julianday(received_at) - 2440587.5) * 86400000) AS INTEGER), raw_message
+FROM room_states_text_ts;
+DROP TABLE room_states_text_ts;
@@ -0,0 +1,15 @@
+ALTER TABLE presence RENAME TO presence_millis;
+DROP INDEX presencechannelindex;
+CREATE TABLE presence (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	login TEXT NOT NULL,
+	event TEXT NOT NULL,
+	received_at TEXT NOT NULL
+);
+CREATE INDEX presencechannelindex ON presence(channel, received_at);
+INSERT INTO presence
+SELECT id, channel, login, event,
+	strftime('%Y-%m-%dT%H:%M:%f+00:00', received_at / 1000.0, 'unixepoch')
+FROM presence_millis;
+DROP TABLE presence_millis;
@@ -0,0 +1,16 @@
+-- presence is stored in milliseconds since the epoch like tmi_sent_ts
+ALTER TABLE presence RENAME TO presence_text_ts;
+DROP INDEX presencechannelindex;
+CREATE TABLE presence (
+	id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+	channel TEXT NOT NULL,
+	login TEXT NOT NULL,
+	event TEXT NOT NULL,
+	received_at BIGINT NOT NULL
+);
+CREATE INDEX presencechannelindex ON presence(channel, received_at);
+INSERT INTO presence
+SELECT id, channel, login, event,
+	CAST(round((julianday(received_at) - 2440587.5) * 86400000) AS INTEGER)
+FROM presence_text_ts;
+DROP TABLE presence_text_ts;
@@ -0,0 +1,7 @@
+DROP TABLE presence;
+DROP TABLE room_states;
+DROP TABLE clear_msgs;
+DROP TABLE clear_chats;
+DROP TABLE user_notices;
+DROP TABLE outages;
+DROP TABLE messages;
@@ -0,0 +1,88 @@
+CREATE TABLE messages (
+	id UUID PRIMARY KEY,
+	badge_info TEXT,
+	badges JSONB,
+	bits INTEGER,
+	color TEXT,
+	display_name TEXT NOT NULL,
+	emotes JSONB,
+	mod BOOLEAN,
+	room_id INTEGER NOT NULL,
+	tmi_sent_ts TIMESTAMPTZ NOT NULL,
+	user_id TEXT NOT NULL,
+	channel TEXT NOT NULL,
+	message TEXT NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX channelindex ON messages(channel, tmi_sent_ts);
+
+CREATE TABLE outages (
+	id BIGSERIAL PRIMARY KEY,
+	channel TEXT NOT NULL,
+	started_at TIMESTAMPTZ NOT NULL,
+	ended_at TIMESTAMPTZ NOT NULL,
+	reason TEXT NOT NULL
+);
+CREATE INDEX outagechannelindex ON outages(channel);
+
+CREATE TABLE user_notices (
+	id UUID PRIMARY KEY,
+	channel TEXT NOT NULL,
+	room_id INTEGER NOT NULL,
+	msg_id TEXT NOT NULL,
+	user_id TEXT,
+	login TEXT,
+	display_name TEXT,
+	system_msg TEXT,
+	message TEXT,
+	params JSONB NOT NULL,
+	tmi_sent_ts TIMESTAMPTZ NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX usernoticechannelindex ON user_notices(channel);
+
+CREATE TABLE clear_chats (
+	id BIGSERIAL PRIMARY KEY,
+	channel TEXT NOT NULL,
+	room_id INTEGER NOT NULL,
+	target_login TEXT,
+	target_user_id TEXT,
+	ban_duration INTEGER,
+	tmi_sent_ts TIMESTAMPTZ NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX clearchatchannelindex ON clear_chats(channel);
+
+CREATE TABLE clear_msgs (
+	id BIGSERIAL PRIMARY KEY,
+	channel TEXT NOT NULL,
+	login TEXT,
+	target_msg_id UUID NOT NULL,
+	message TEXT,
+	tmi_sent_ts TIMESTAMPTZ NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX clearmsgchannelindex ON clear_msgs(channel);
+
+CREATE TABLE room_states (
+	id BIGSERIAL PRIMARY KEY,
+	channel TEXT NOT NULL,
+	room_id INTEGER NOT NULL,
+	emote_only BOOLEAN,
+	followers_only INTEGER,
+	r9k BOOLEAN,
+	slow INTEGER,
+	subs_only BOOLEAN,
+	received_at TIMESTAMPTZ NOT NULL,
+	raw_message TEXT NOT NULL
+);
+CREATE INDEX roomstatechannelindex ON room_states(channel);
+
+CREATE TABLE presence (
+	id BIGSERIAL PRIMARY KEY,
+	channel TEXT NOT NULL,
+	login TEXT NOT NULL,
+	event TEXT NOT NULL,
+	received_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX presencechannelindex ON presence(channel, received_at);
@@ -0,0 +1,2 @@
+DROP TABLE message_emotes;
+DROP TABLE message_badges;
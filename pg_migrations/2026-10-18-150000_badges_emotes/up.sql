@@ -0,0 +1,30 @@
+CREATE TABLE message_badges (
+	message_id UUID NOT NULL,
+	name TEXT NOT NULL,
+	version TEXT NOT NULL,
+	PRIMARY KEY (message_id, name)
+);
+CREATE INDEX messagebadgeindex ON message_badges(name, version);
+
+CREATE TABLE message_emotes (
+	message_id UUID NOT NULL,
+	emote_id TEXT NOT NULL,
+	start_index INTEGER NOT NULL,
+	end_index INTEGER NOT NULL,
+	PRIMARY KEY (message_id, start_index)
+);
+CREATE INDEX messageemoteindex ON message_emotes(emote_id);
+
+-- fill in from the badges and emotes json of existing messages
+INSERT INTO message_badges
+SELECT m.id, split_part(b, '/', 1), split_part(b, '/', 2)
+FROM messages m, jsonb_array_elements_text(m.badges) b
+WHERE strpos(b, '/') > 0
+ON CONFLICT DO NOTHING;
+
+INSERT INTO message_emotes
+SELECT m.id, split_part(e, ':', 1), split_part(r, '-', 1)::INTEGER, split_part(r, '-', 2)::INTEGER
+FROM messages m, jsonb_array_elements_text(m.emotes) e,
+	unnest(string_to_array(split_part(e, ':', 2), ',')) r
+WHERE strpos(r, '-') > 0
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,5 @@
+-- substr counts characters, which is how twitch gives emote positions
+ALTER TABLE message_emotes ADD COLUMN name TEXT NOT NULL DEFAULT '';
+UPDATE message_emotes e SET name = substr(m.message, e.start_index + 1, e.end_index - e.start_index + 1)
+FROM messages m WHERE m.id = e.message_id;
+CREATE INDEX messageemotenameindex ON message_emotes(name);
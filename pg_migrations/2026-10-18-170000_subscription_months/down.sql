@@ -0,0 +1 @@
+ALTER TABLE messages DROP COLUMN subscription_months;
@@ -0,0 +1,4 @@
+-- founders count their months the same way as subscribers
+ALTER TABLE messages ADD COLUMN subscription_months INTEGER;
+UPDATE messages SET subscription_months = substring(badge_info from '(?:subscriber|founder)/(\d+)')::int
+WHERE badge_info ~ '(?:subscriber|founder)/\d+';
@@ -0,0 +1,2 @@
+UPDATE messages SET message = chr(1) || 'ACTION ' || message || chr(1) WHERE action;
+ALTER TABLE messages DROP COLUMN action;
@@ -0,0 +1,8 @@
+-- /me messages were stored with their \x01ACTION ... \x01 wrapper. Twitch counts emote positions
+-- from the text inside, so the names of their emotes are taken again once it's removed.
+ALTER TABLE messages ADD COLUMN action BOOLEAN NOT NULL DEFAULT false;
+UPDATE messages SET action = true,
+	message = substr(message, 9, length(message) - 8 - CASE WHEN right(message, 1) = chr(1) THEN 1 ELSE 0 END)
+WHERE left(message, 8) = chr(1) || 'ACTION ';
+UPDATE message_emotes e SET name = substr(m.message, e.start_index + 1, e.end_index - e.start_index + 1)
+FROM messages m WHERE m.id = e.message_id AND m.action;
@@ -0,0 +1,3 @@
+ALTER TABLE outages
+	ALTER COLUMN started_at TYPE TIMESTAMPTZ USING to_timestamp(started_at / 1000.0),
+	ALTER COLUMN ended_at TYPE TIMESTAMPTZ USING to_timestamp(ended_at / 1000.0);
@@ -0,0 +1,4 @@
+-- outages are stored in milliseconds since the epoch, the same as on sqlite
+ALTER TABLE outages
+	ALTER COLUMN started_at TYPE BIGINT USING floor(extract(epoch FROM started_at) * 1000)::bigint,
+	ALTER COLUMN ended_at TYPE BIGINT USING floor(extract(epoch FROM ended_at) * 1000)::bigint;
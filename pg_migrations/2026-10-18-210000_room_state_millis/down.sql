@@ -0,0 +1,2 @@
+ALTER TABLE room_states
+	ALTER COLUMN received_at TYPE TIMESTAMPTZ USING to_timestamp(received_at / 1000.0);
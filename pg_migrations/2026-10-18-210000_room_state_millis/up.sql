@@ -0,0 +1,3 @@
+-- room states are stored in milliseconds since the epoch, the same as on sqlite
+ALTER TABLE room_states
+	ALTER COLUMN received_at TYPE BIGINT USING floor(extract(epoch FROM received_at) * 1000)::bigint;
@@ -0,0 +1 @@
+SELECT channel, strftime('%Y-%m-%d %H:00', received_at) AS hour, COUNT(DISTINCT login) FROM presence WHERE event = 'join' GROUP BY channel, hour ORDER BY channel, hour;
@@ -0,0 +1 @@
+SELECT date(m.tmi_sent_ts / 1000, 'unixepoch') AS day, e.name, COUNT(*) AS uses FROM message_emotes e JOIN messages m ON m.id = e.message_id GROUP BY day, e.name ORDER BY day, uses DESC;
@@ -0,0 +1 @@
+SELECT channel, COUNT(*), MIN(started_at), MAX(ended_at) FROM outages GROUP BY channel ORDER BY COUNT(*) DESC;
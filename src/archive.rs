@@ -0,0 +1,454 @@
+use crate::db::MessageSink;
+use crate::error::MyError;
+use crate::types::Record;
+use chrono::{DateTime, Timelike, Utc};
+use flate2::write::GzEncoder;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "ndjson",
+            Compression::Gzip => "ndjson.gz",
+            Compression::Zstd => "ndjson.zst",
+        }
+    }
+}
+
+///Settings for writing records to newline delimited json files
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveConfig {
+    pub directory: PathBuf,
+    pub compression: Compression,
+    ///Start a new file every hour. Files are always started on a new day.
+    pub rotate_hourly: bool,
+    ///Start a new file once one reaches this many bytes on disk
+    pub max_file_size: Option<u64>,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        ArchiveConfig {
+            directory: PathBuf::from("archive"),
+            compression: Compression::Zstd,
+            rotate_hourly: true,
+            max_file_size: None,
+        }
+    }
+}
+
+///Counts the bytes that reach the file, after compression
+struct Counted {
+    file: File,
+    bytes: u64,
+}
+
+impl Write for Counted {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+enum Encoder {
+    None(BufWriter<Counted>),
+    Gzip(GzEncoder<Counted>),
+    Zstd(zstd::Encoder<Counted>),
+}
+
+impl Encoder {
+    fn new(compression: Compression, file: File) -> io::Result<Encoder> {
+        let counted = Counted { file, bytes: 0 };
+        Ok(match compression {
+            Compression::None => Encoder::None(BufWriter::new(counted)),
+            Compression::Gzip => {
+                Encoder::Gzip(GzEncoder::new(counted, flate2::Compression::default()))
+            }
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(counted, 0)?),
+        })
+    }
+
+    fn bytes_written(&self) -> u64 {
+        match self {
+            Encoder::None(w) => w.get_ref().bytes,
+            Encoder::Gzip(w) => w.get_ref().bytes,
+            Encoder::Zstd(w) => w.get_ref().bytes,
+        }
+    }
+
+    ///Writes out anything buffered, including the compression footer
+    fn finish(self) -> io::Result<File> {
+        let counted = match self {
+            Encoder::None(w) => w.into_inner().map_err(|e| e.into_error())?,
+            Encoder::Gzip(w) => w.finish()?,
+            Encoder::Zstd(w) => w.finish()?,
+        };
+        Ok(counted.file)
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::None(w) => w.write(buf),
+            Encoder::Gzip(w) => w.write(buf),
+            Encoder::Zstd(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::None(w) => w.flush(),
+            Encoder::Gzip(w) => w.flush(),
+            Encoder::Zstd(w) => w.flush(),
+        }
+    }
+}
+
+struct OpenFile {
+    encoder: Encoder,
+    path: PathBuf,
+    opened: DateTime<Utc>,
+}
+
+impl OpenFile {
+    fn close(self) -> io::Result<()> {
+        self.encoder.finish()?.sync_all()
+    }
+}
+
+///Writes each channel's records to its own newline delimited json files, at
+///`directory/channel/yyyy-mm-dd/hhmmss.ndjson[.gz|.zst]`. One file is kept open per channel until
+///it's rotated, and files are synced to disk when they're closed. Files are flushed after each
+///batch so everything but the compression footer is written out while they're open.
+pub struct FileSink {
+    config: ArchiveConfig,
+    files: HashMap<String, OpenFile>,
+}
+
+impl FileSink {
+    pub fn new(config: ArchiveConfig) -> Result<FileSink, MyError> {
+        fs::create_dir_all(&config.directory)?;
+        Ok(FileSink {
+            config,
+            files: HashMap::new(),
+        })
+    }
+
+    fn write_at(&mut self, records: Vec<Record>, now: DateTime<Utc>) -> Result<usize, MyError> {
+        self.close_due(now)?;
+        let count = records.len();
+        let mut written = HashSet::new();
+        for record in records {
+            let file = self.file_for(record.channel(), now)?;
+            serde_json::to_writer(&mut file.encoder, &record)
+                .map_err(|e| MyError::Other(Box::new(e)))?;
+            file.encoder.write_all(b"\n")?;
+            written.insert(sanitise(record.channel()));
+        }
+        for channel in written {
+            //files that were rotated part way through the batch are already closed
+            if let Some(file) = self.files.get_mut(&channel) {
+                file.encoder.flush()?;
+            }
+        }
+        Ok(count)
+    }
+
+    ///The file to write a channel's records to, closing the current one if it's due to be rotated
+    fn file_for(&mut self, channel: &str, now: DateTime<Utc>) -> io::Result<&mut OpenFile> {
+        let channel = sanitise(channel);
+        if let Some(file) = self.files.get(&channel) {
+            if self.should_rotate(file, now) {
+                self.files.remove(&channel).unwrap().close()?;
+            }
+        }
+        if !self.files.contains_key(&channel) {
+            let path = self.new_path(&channel, now);
+            fs::create_dir_all(path.parent().unwrap())?;
+            let file = OpenFile {
+                encoder: Encoder::new(self.config.compression, File::create(&path)?)?,
+                path,
+                opened: now,
+            };
+            self.files.insert(channel.clone(), file);
+        }
+        Ok(self.files.get_mut(&channel).unwrap())
+    }
+
+    ///Closes every file that's due to be rotated, so a channel that's gone quiet doesn't keep its
+    ///last file open until it's written to again
+    fn close_due(&mut self, now: DateTime<Utc>) -> io::Result<()> {
+        let due: Vec<String> = self
+            .files
+            .iter()
+            .filter(|(_, file)| self.should_rotate(file, now))
+            .map(|(channel, _)| channel.clone())
+            .collect();
+        for channel in due {
+            self.files.remove(&channel).unwrap().close()?;
+        }
+        Ok(())
+    }
+
+    fn should_rotate(&self, file: &OpenFile, now: DateTime<Utc>) -> bool {
+        let max_size = self.config.max_file_size.unwrap_or(u64::MAX);
+        file.opened.date_naive() != now.date_naive()
+            || (self.config.rotate_hourly && file.opened.hour() != now.hour())
+            || file.encoder.bytes_written() >= max_size
+    }
+
+    fn new_path(&self, channel: &str, now: DateTime<Utc>) -> PathBuf {
+        let dir = self
+            .config
+            .directory
+            .join(channel)
+            .join(now.format("%Y-%m-%d").to_string());
+        let name = now.format("%H%M%S").to_string();
+        let ext = self.config.compression.extension();
+        let mut path = dir.join(format!("{}.{}", name, ext));
+        //several files can be started in a second when rotating by size
+        let mut n = 1;
+        while path.exists() {
+            path = dir.join(format!("{}-{}.{}", name, n, ext));
+            n += 1;
+        }
+        path
+    }
+
+    ///Paths of the files currently being written to
+    pub fn open_files(&self) -> Vec<&Path> {
+        self.files.values().map(|f| f.path.as_path()).collect()
+    }
+}
+
+impl MessageSink for FileSink {
+    fn write(&mut self, records: Vec<Record>) -> Result<usize, MyError> {
+        self.write_at(records, Utc::now())
+    }
+}
+
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        for (_, file) in self.files.drain() {
+            let path = file.path.clone();
+            if let Err(e) = file.close() {
+                eprintln!("[{}] error closing {}: {}", Utc::now(), path.display(), e);
+            }
+        }
+    }
+}
+
+///Channel names are only meant to be alphanumeric or _ but don't trust them in paths
+pub(crate) fn sanitise(channel: &str) -> String {
+    let channel: String = channel
+        .trim_start_matches('#')
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if channel.is_empty() {
+        "_".to_string()
+    } else {
+        channel
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::samples;
+    use chrono::{Duration, TimeZone};
+    use std::io::{BufRead, BufReader, Read};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("archive-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn config(name: &str, compression: Compression) -> ArchiveConfig {
+        ArchiveConfig {
+            directory: temp_dir(name),
+            compression,
+            ..ArchiveConfig::default()
+        }
+    }
+
+    fn files(dir: &Path) -> Vec<PathBuf> {
+        let mut found = Vec::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                found.extend(files(&path));
+            } else {
+                found.push(path);
+            }
+        }
+        found.sort();
+        found
+    }
+
+    fn read_records(path: &Path, compression: Compression) -> Vec<Record> {
+        let file = File::open(path).unwrap();
+        let reader: Box<dyn Read> = match compression {
+            Compression::None => Box::new(file),
+            Compression::Gzip => Box::new(flate2::read::GzDecoder::new(file)),
+            Compression::Zstd => Box::new(zstd::Decoder::new(file).unwrap()),
+        };
+        BufReader::new(reader)
+            .lines()
+            .map(|l| serde_json::from_str(&l.unwrap()).unwrap())
+            .collect()
+    }
+
+    fn at(h: u32, m: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 5, 7, h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for &compression in &[Compression::None, Compression::Gzip, Compression::Zstd] {
+            let config = config(&format!("{:?}", compression), compression);
+            let dir = config.directory.clone();
+            let mut sink = FileSink::new(config).unwrap();
+            sink.write_at(samples::records(), at(10, 0)).unwrap();
+            drop(sink);
+
+            let found = files(&dir);
+            //records are from #bar, #dallas and #ronni
+            assert_eq!(found.len(), 3);
+            let dallas = dir.join(format!(
+                "dallas/2020-05-07/100000.{}",
+                compression.extension()
+            ));
+            assert_eq!(found[1], dallas);
+            let records: Vec<Record> = found
+                .iter()
+                .flat_map(|f| read_records(f, compression))
+                .collect();
+            assert_eq!(records.len(), samples::records().len());
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_hourly_rotation() {
+        let config = config("hourly", Compression::Gzip);
+        let dir = config.directory.clone();
+        let mut sink = FileSink::new(config).unwrap();
+        let message = || vec![Record::Message(samples::message())];
+        sink.write_at(message(), at(10, 0)).unwrap();
+        sink.write_at(message(), at(10, 59)).unwrap();
+        let first = sink.open_files()[0].to_path_buf();
+        sink.write_at(message(), at(11, 0)).unwrap();
+        sink.write_at(message(), at(11, 0) + Duration::days(1))
+            .unwrap();
+
+        //the rotated files are complete before the sink is dropped
+        assert_eq!(read_records(&first, Compression::Gzip).len(), 2);
+        drop(sink);
+        let found = files(&dir);
+        assert_eq!(found.len(), 3);
+        assert!(found[2].starts_with(dir.join("ronni/2020-05-08")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_quiet_channels_are_rotated() {
+        let config = config("quiet", Compression::Zstd);
+        let dir = config.directory.clone();
+        let mut sink = FileSink::new(config).unwrap();
+        sink.write_at(samples::records(), at(10, 0)).unwrap();
+        assert_eq!(sink.open_files().len(), 3);
+        sink.write_at(vec![Record::Message(samples::message())], at(11, 0))
+            .unwrap();
+
+        //only #ronni has been written to since, the others are closed and complete
+        assert_eq!(sink.open_files().len(), 1);
+        let dallas = dir.join("dallas/2020-05-07/100000.ndjson.zst");
+        assert!(!read_records(&dallas, Compression::Zstd).is_empty());
+        drop(sink);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_size_rotation() {
+        let config = ArchiveConfig {
+            rotate_hourly: false,
+            max_file_size: Some(1),
+            ..config("size", Compression::None)
+        };
+        let dir = config.directory.clone();
+        let mut sink = FileSink::new(config).unwrap();
+        for _ in 0..3 {
+            sink.write_at(vec![Record::Message(samples::message())], at(10, 0))
+                .unwrap();
+        }
+        drop(sink);
+        let found = files(&dir);
+        assert_eq!(found.len(), 3);
+        assert!(found
+            .iter()
+            .all(|f| read_records(f, Compression::None).len() == 1));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_files_are_flushed_after_each_write() {
+        for &compression in &[Compression::None, Compression::Gzip, Compression::Zstd] {
+            let config = config(&format!("flushed-{:?}", compression), compression);
+            let dir = config.directory.clone();
+            let mut sink = FileSink::new(config).unwrap();
+            sink.write_at(samples::records(), at(10, 0)).unwrap();
+
+            //the files aren't finished yet, so read up to where the compressed stream stops
+            let read: usize = sink
+                .open_files()
+                .iter()
+                .map(|f| {
+                    let file = File::open(f).unwrap();
+                    let reader: Box<dyn Read> = match compression {
+                        Compression::None => Box::new(file),
+                        Compression::Gzip => Box::new(flate2::read::GzDecoder::new(file)),
+                        Compression::Zstd => Box::new(zstd::Decoder::new(file).unwrap()),
+                    };
+                    BufReader::new(reader).lines().map_while(Result::ok).count()
+                })
+                .sum();
+            assert_eq!(read, samples::records().len());
+            drop(sink);
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sanitise() {
+        assert_eq!(sanitise("#some_channel"), "some_channel");
+        assert_eq!(sanitise("../etc"), "___etc");
+        assert_eq!(sanitise("#"), "_");
+    }
+}
@@ -1,10 +1,17 @@
+use crate::error::MyError;
+use chrono::Utc;
 use futures::stream::{self, StreamExt};
-use lazy_static::lazy_static;
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::iter::FromIterator;
+use std::time::Duration;
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Pagination {
-    cursor: String,
+    ///Missing on the last page
+    cursor: Option<String>,
 }
 
 //other fields are included for now for interest. Maybe remove in the future because
@@ -33,6 +40,7 @@ struct ChannelResponse {
 
 impl ChannelResponse {
     async fn get(
+        helix: &Helix,
         number: u64,
         pagination: Option<String>,
     ) -> Result<ChannelResponse, Box<dyn std::error::Error>> {
@@ -40,11 +48,12 @@ impl ChannelResponse {
         if let Some(page) = pagination {
             params.push(("after", page));
         }
-        request("streams", params).await
+        helix.request("streams", params).await
     }
 }
 
 struct ChannelPages {
+    helix: Helix,
     page: Option<String>,
     number: u64,
 }
@@ -58,18 +67,24 @@ async fn pages(channel_pages: ChannelPages) -> Option<(ChannelResponse, ChannelP
 
     //TODO use overflowing sub here with MAX_PER_PAGE?
     let new_to_get = channel_pages.number.saturating_sub(to_get);
-    match ChannelResponse::get(to_get, channel_pages.page).await {
+    match ChannelResponse::get(&channel_pages.helix, to_get, channel_pages.page).await {
         Ok(r) => {
             let curs = r.pagination.cursor.clone();
+            //no cursor means there are no more live channels
+            let new_to_get = if curs.is_some() { new_to_get } else { 0 };
             Some((
                 r,
                 ChannelPages {
-                    page: Some(curs),
+                    helix: channel_pages.helix,
+                    page: curs,
                     number: new_to_get,
                 },
             ))
         }
-        Err(_) => None,
+        Err(e) => {
+            eprintln!("[{}] error getting top channels: {}", Utc::now(), e);
+            None
+        }
     }
 }
 
@@ -77,48 +92,180 @@ async fn pages(channel_pages: ChannelPages) -> Option<(ChannelResponse, ChannelP
 const CLIENT_ID: &str = "jzkbprff40iqj646a697cyrvl0zt2m6";
 const API_URL: &str = "https://api.twitch.tv/helix/";
 const MAX_PER_PAGE: u64 = 100;
+///Times a rate limited request is retried before giving up
+const RATE_LIMIT_RETRIES: usize = 3;
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+///Credentials sent with every helix request. Newer helix endpoints need an app access token
+///as well as the client id.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct HelixCredentials {
+    pub client_id: String,
+    pub token: Option<String>,
+}
+
+impl Default for HelixCredentials {
+    fn default() -> Self {
+        HelixCredentials {
+            client_id: CLIENT_ID.to_string(),
+            token: None,
+        }
+    }
+}
+
+///Client for the helix API. Cheap to clone.
+#[derive(Clone)]
+pub struct Helix {
+    client: reqwest::Client,
+    base_url: reqwest::Url,
+}
+
+impl Helix {
+    pub fn new(credentials: &HelixCredentials) -> Result<Helix, MyError> {
+        Helix::with_base_url(credentials, API_URL)
+    }
+
+    ///Sends requests somewhere other than twitch, eg - a mock server in tests
+    pub fn with_base_url(credentials: &HelixCredentials, base_url: &str) -> Result<Helix, MyError> {
+        //endpoints are joined on, which replaces the last segment without a trailing /
+        let base_url = if base_url.ends_with('/') {
+            base_url.to_string()
+        } else {
+            format!("{}/", base_url)
+        };
+        let base_url = reqwest::Url::parse(&base_url)
+            .map_err(|e| MyError::Config(format!("bad helix url {}: {}", base_url, e)))?;
 
-lazy_static! {
-    static ref CLIENT: reqwest::Client = {
         let mut header_map = reqwest::header::HeaderMap::new();
-        header_map.insert("Client-ID", CLIENT_ID.parse().unwrap());
+        header_map.insert("Client-ID", header_value(&credentials.client_id)?);
+        if let Some(token) = &credentials.token {
+            header_map.insert(
+                reqwest::header::AUTHORIZATION,
+                header_value(&format!("Bearer {}", token))?,
+            );
+        }
 
-        reqwest::Client::builder()
+        let client = reqwest::Client::builder()
             .default_headers(header_map)
             .build()
-            .unwrap()
-    };
+            .map_err(|e| MyError::Other(Box::new(e)))?;
+        Ok(Helix { client, base_url })
+    }
+
+    //TODO- lazy reusable request builder for best performance
+    async fn request<T>(
+        &self,
+        endpoint: &str,
+        params: Vec<(&str, String)>,
+    ) -> Result<T, Box<dyn std::error::Error>>
+    where
+        T: std::marker::Sized + serde::de::DeserializeOwned,
+    {
+        let url = reqwest::Url::parse_with_params(self.base_url.join(endpoint)?.as_str(), &params)?;
+        let mut retries = 0;
+        loop {
+            let res = self.client.get(url.clone()).send().await?;
+            let status = res.status();
+            if status == StatusCode::TOO_MANY_REQUESTS && retries < RATE_LIMIT_RETRIES {
+                retries += 1;
+                let wait = rate_limit_wait(res.headers());
+                eprintln!(
+                    "[{}] helix rate limit reached, retrying {} in {:?}",
+                    Utc::now(),
+                    endpoint,
+                    wait
+                );
+                tokio::time::delay_for(wait).await;
+                continue;
+            }
+            if !status.is_success() {
+                //the body says why, eg - {"error":"Unauthorized","status":401,"message":"..."}
+                let body = res.text().await.unwrap_or_default();
+                return Err(format!("helix {} returned {}: {}", endpoint, status, body).into());
+            }
+            return Ok(res.json().await?);
+        }
+    }
+}
+
+///How long until the rate limit resets, from the Ratelimit-Reset header (a unix timestamp)
+fn rate_limit_wait(headers: &HeaderMap) -> Duration {
+    let reset = headers
+        .get("Ratelimit-Reset")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<i64>().ok());
+    match reset {
+        Some(reset) => {
+            let secs = (reset - Utc::now().timestamp()).max(0) as u64;
+            std::cmp::min(Duration::from_secs(secs), MAX_RATE_LIMIT_WAIT)
+        }
+        None => Duration::from_secs(1),
+    }
 }
 
-//TODO- lazy reusable request builder for best performance
-//probably need to make this a struct for that
-async fn request<T>(
-    endpoint: &str,
-    params: Vec<(&str, String)>,
-) -> Result<T, Box<dyn std::error::Error>>
-where
-    T: std::marker::Sized + serde::de::DeserializeOwned,
-{
-    let url = reqwest::Url::parse_with_params(&(API_URL.to_owned() + endpoint), &params)?;
-    let res = CLIENT.get(&url.into_string()).send().await?.json().await?;
-    Ok(res)
-}
-
-pub async fn top_connections(number: u64) -> Vec<String> {
-    stream::unfold(ChannelPages { page: None, number }, pages)
-        .then(|page| async move {
-            let ids: Vec<String> = page.data.into_iter().map(|x| x.user_id).collect();
-            // The ChannelPages iterator already returns up to the max of this endpoint anyway so it's
-            // OK to keep this in the loop
-            // TODO but it shouldn't unwrap
-            let resp = UserResponse::get_login_names(ids).await.unwrap();
-            stream::iter(resp.data.into_iter().map(|u| u.login))
+fn header_value(s: &str) -> Result<reqwest::header::HeaderValue, MyError> {
+    s.parse()
+        .map_err(|_| MyError::Config(format!("'{}' is not a valid header value", s)))
+}
+
+pub async fn top_connections(helix: &Helix, number: u64) -> Vec<String> {
+    let start = ChannelPages {
+        helix: helix.clone(),
+        page: None,
+        number,
+    };
+    stream::unfold(start, pages)
+        .then(|page| {
+            let helix = helix.clone();
+            async move {
+                let ids: Vec<String> = page.data.into_iter().map(|x| x.user_id).collect();
+                // The ChannelPages iterator already returns up to the max of this endpoint anyway so it's
+                // OK to keep this in the loop
+                let logins = match UserResponse::get_login_names(&helix, ids).await {
+                    Ok(resp) => resp.data.into_iter().map(|u| u.login).collect(),
+                    Err(e) => {
+                        eprintln!("[{}] error getting channel names: {}", Utc::now(), e);
+                        vec![]
+                    }
+                };
+                stream::iter(logins)
+            }
         })
         .flatten()
         .collect::<Vec<String>>()
         .await
 }
 
+///Removes duplicate channels returned by the API, warning if fewer than expected remain
+pub(crate) fn cleanup_channels(mut chans: Vec<String>, expected: u64) -> Vec<String> {
+    let mut seen_set = HashSet::<String>::with_capacity(chans.len());
+
+    chans.retain(|c| {
+        let seen = !seen_set.insert(c.to_string());
+        if seen {
+            eprintln!(
+                "channel {} was found twice in channels returned by API, removing duplicate",
+                c
+            );
+        }
+        !seen
+    });
+    if chans.len() < expected as usize {
+        eprintln!(
+            "API returned fewer channels than expected. Expected {}, got {}",
+            expected,
+            chans.len()
+        );
+    }
+    assert_eq!(
+        chans.len(),
+        HashSet::<&String>::from_iter(chans.iter()).len()
+    ); //check there are no duplicates
+
+    chans
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct UserJson {
     broadcaster_type: String,
@@ -141,28 +288,149 @@ impl UserResponse {
     //TODO - Sometimes this seems to return fewer channels than requested. Maybe return an error
     //for this too
     async fn get_login_names(
+        helix: &Helix,
         userids: Vec<String>,
     ) -> Result<UserResponse, Box<dyn std::error::Error>> {
         let params: Vec<(&str, String)> = userids.into_iter().map(|s| ("id", s)).collect();
-        request("users", params).await
+        helix.request("users", params).await
     }
 }
 
-#[test]
-fn test_get_login_names() {
-    let resp = UserResponse::get_login_names(vec!["23161357".to_string()]).unwrap();
-    assert!(resp.data.len() == 1);
-    assert_eq!(resp.data[0].display_name, "LIRIK");
-}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fake_helix::{FakeHelix, FakeStream};
 
-#[test]
-fn test_top_connections() {
-    let resp = top_connections(10);
-    assert_eq!(resp.len(), 10);
-}
+    fn helix() -> Helix {
+        Helix::new(&HelixCredentials::default()).unwrap()
+    }
+
+    fn live(n: usize) -> Vec<FakeStream> {
+        (0..n)
+            .map(|i| FakeStream::new(&i.to_string(), &format!("channel{}", i)))
+            .collect()
+    }
+
+    fn fake_helix(server: &FakeHelix) -> Helix {
+        let credentials = HelixCredentials {
+            token: Some("token".to_string()),
+            ..HelixCredentials::default()
+        };
+        Helix::with_base_url(&credentials, &server.url()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_top_connections_follows_cursors() {
+        let server = FakeHelix::start(live(250)).await;
+        let logins = top_connections(&fake_helix(&server), 1000).await;
+        assert_eq!(logins.len(), 250);
+        assert_eq!(logins[0], "channel0");
+        assert_eq!(logins[249], "channel249");
+
+        let requests = server.requests();
+        let streams: Vec<_> = requests
+            .iter()
+            .filter(|r| r.endpoint == "streams")
+            .collect();
+        //the last page has no cursor so there's no request after it
+        assert_eq!(streams.len(), 3);
+        assert_eq!(streams[0].param("after"), None);
+        assert_eq!(streams[1].param("after"), Some("100"));
+        assert_eq!(streams[2].param("first"), Some("100"));
+        assert_eq!(requests.len(), 6);
+        assert_eq!(
+            requests[0].headers.get("authorization").map(String::as_str),
+            Some("Bearer token")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_top_connections_stops_at_number() {
+        let server = FakeHelix::start(live(250)).await;
+        let logins = top_connections(&fake_helix(&server), 150).await;
+        assert_eq!(logins.len(), 150);
+        let requests = server.requests();
+        assert_eq!(requests[2].param("first"), Some("50"));
+    }
 
-#[test]
-fn test_channel_response() {
-    let resp = ChannelResponse::get(4, None).unwrap();
-    assert_eq!(4, resp.data.len());
+    #[tokio::test]
+    async fn test_duplicates_are_removed() {
+        //a channel moving up the list between pages is returned twice
+        let mut streams = live(150);
+        streams.insert(120, FakeStream::new("42", "channel42"));
+        let server = FakeHelix::start(streams).await;
+        let logins = top_connections(&fake_helix(&server), 151).await;
+        assert_eq!(logins.len(), 151);
+        let channels = cleanup_channels(logins, 151);
+        assert_eq!(channels.len(), 150);
+        assert_eq!(channels.iter().filter(|c| *c == "channel42").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_requests_are_retried() {
+        let server = FakeHelix::start(live(5)).await;
+        server.rate_limit_next("users");
+        server.rate_limit_next("users");
+        let resp = UserResponse::get_login_names(&fake_helix(&server), vec!["3".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(resp.data[0].login, "channel3");
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_error_responses() {
+        let server = FakeHelix::start(live(5)).await;
+        let helix = fake_helix(&server);
+        server.fail_next("users", 401, "Invalid OAuth token");
+        let err = UserResponse::get_login_names(&helix, vec!["3".to_string()])
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("401"), "{}", err);
+        assert!(err.contains("Invalid OAuth token"), "{}", err);
+
+        //a failed page of names is left out instead of stopping everything
+        let server = FakeHelix::start(live(150)).await;
+        let helix = fake_helix(&server);
+        server.fail_next("users", 500, "Internal Server Error");
+        let logins = top_connections(&helix, 150).await;
+        assert_eq!(logins.len(), 50);
+        assert_eq!(logins[0], "channel100");
+        //and a failed page of streams ends the list
+        server.fail_next("streams", 500, "Internal Server Error");
+        assert!(top_connections(&helix, 150).await.is_empty());
+        assert_eq!(top_connections(&helix, 150).await.len(), 150);
+
+        //rate limits are only waited out a few times
+        for _ in 0..=RATE_LIMIT_RETRIES {
+            server.rate_limit_next("streams");
+        }
+        let err = ChannelResponse::get(&helix, 5, None).await.unwrap_err();
+        assert!(err.to_string().contains("429"), "{}", err);
+    }
+
+    #[tokio::test]
+    #[ignore] //hits the live helix API
+    async fn test_get_login_names() {
+        let resp = UserResponse::get_login_names(&helix(), vec!["23161357".to_string()])
+            .await
+            .unwrap();
+        assert!(resp.data.len() == 1);
+        assert_eq!(resp.data[0].display_name, "LIRIK");
+    }
+
+    #[tokio::test]
+    #[ignore] //hits the live helix API
+    async fn test_top_connections() {
+        let resp = top_connections(&helix(), 10).await;
+        assert_eq!(resp.len(), 10);
+    }
+
+    #[tokio::test]
+    #[ignore] //hits the live helix API
+    async fn test_channel_response() {
+        let resp = ChannelResponse::get(&helix(), 4, None).await.unwrap();
+        assert_eq!(4, resp.data.len());
+    }
 }
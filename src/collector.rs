@@ -0,0 +1,382 @@
+use crate::archive::{ArchiveConfig, FileSink};
+use crate::channels::{self, Helix, HelixCredentials};
+use crate::config::Config;
+use crate::db::{MessageSink, DB, DEFAULT_BATCH_SIZE};
+use crate::error::MyError;
+use crate::refresh::RefreshConfig;
+use crate::replay::{self, ReplayConfig};
+use crate::sender::RecordSender;
+use crate::state::ChannelTracker;
+use crate::twitchclient::{self, ClientConfig};
+use crate::types::Record;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+use tokio::sync::mpsc as async_mpsc;
+
+const DEFAULT_MAX_CHANNELS: u64 = 1000;
+
+///Where the channels to join come from
+#[derive(Debug, Clone)]
+pub enum Channels {
+    ///The top n live channels by viewer count, as returned by the helix API
+    Top(u64),
+    ///An explicit list of channel names
+    List(Vec<String>),
+}
+
+///How messages are received from twitch
+#[derive(Debug, Clone)]
+pub enum Transport {
+    Irc,
+    ///Replay a file of raw IRC lines instead of connecting. Channels aren't looked up or joined.
+    Replay(ReplayConfig),
+}
+
+///Where parsed messages are sent
+pub enum Sink {
+    ///Batch insert into a sqlite or postgres database, depending on the url
+    Db {
+        database_url: String,
+        batch_size: usize,
+    },
+    ///Write newline delimited json files
+    Archive {
+        config: ArchiveConfig,
+        batch_size: usize,
+    },
+    ///Batch write to any other sink
+    Custom {
+        sink: Box<dyn MessageSink>,
+        batch_size: usize,
+    },
+    ///Hand messages (and outages) to the caller
+    Sender(Sender<Record>),
+    ///Hand messages (and outages) to the caller through a bounded channel. See
+    ///`CollectorBuilder::channel`.
+    Channel(async_mpsc::Sender<Record>),
+}
+
+pub struct CollectorBuilder {
+    channels: Channels,
+    transport: Transport,
+    sink: Sink,
+    helix: HelixCredentials,
+    refresh_interval: Option<Duration>,
+    client: ClientConfig,
+}
+
+impl Default for CollectorBuilder {
+    fn default() -> Self {
+        CollectorBuilder {
+            channels: Channels::Top(DEFAULT_MAX_CHANNELS),
+            transport: Transport::Irc,
+            sink: Sink::Db {
+                database_url: "db.sqlite".to_string(),
+                batch_size: DEFAULT_BATCH_SIZE,
+            },
+            helix: HelixCredentials::default(),
+            refresh_interval: None,
+            client: ClientConfig::default(),
+        }
+    }
+}
+
+impl CollectorBuilder {
+    pub fn channels(mut self, channels: Channels) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn sink(mut self, sink: Sink) -> Self {
+        self.sink = sink;
+        self
+    }
+
+    ///Don't store anything, instead return a receiver for everything collected. It holds up to
+    ///`capacity` records and collection waits while it's full. Use `sender::messages` to only
+    ///get chat messages.
+    pub fn channel(self, capacity: usize) -> (Self, async_mpsc::Receiver<Record>) {
+        let (sender, receiver) = async_mpsc::channel(capacity);
+        (self.sink(Sink::Channel(sender)), receiver)
+    }
+
+    pub fn helix(mut self, helix: HelixCredentials) -> Self {
+        self.helix = helix;
+        self
+    }
+
+    ///Periodically update the joined channels to the current top channels. Only used with
+    ///`Channels::Top`.
+    pub fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = Some(interval);
+        self
+    }
+
+    pub fn client(mut self, client: ClientConfig) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn build(self) -> Collector {
+        Collector {
+            channels: self.channels,
+            transport: self.transport,
+            sink: self.sink,
+            helix: self.helix,
+            refresh_interval: self.refresh_interval,
+            client: self.client,
+            tracker: ChannelTracker::new(),
+        }
+    }
+}
+
+impl From<&Config> for CollectorBuilder {
+    fn from(config: &Config) -> Self {
+        let channels = match &config.channels {
+            Some(list) => Channels::List(list.clone()),
+            None => Channels::Top(config.max_channels),
+        };
+        let mut builder = Collector::builder()
+            .channels(channels)
+            .helix(config.helix.clone())
+            .refresh_interval(Duration::from_secs(config.refresh_interval))
+            .client(ClientConfig {
+                channels_per_connection: config.channels_per_connection,
+                capabilities: config.capabilities,
+                server: config.irc.clone(),
+                ..ClientConfig::default()
+            });
+        if let Some(archive) = &config.archive {
+            builder = builder.sink(Sink::Archive {
+                config: archive.clone(),
+                batch_size: config.batch_size,
+            });
+        } else if let Some(database_url) = &config.database_url {
+            builder = builder.sink(Sink::Db {
+                database_url: database_url.clone(),
+                batch_size: config.batch_size,
+            });
+        }
+        builder
+    }
+}
+
+pub struct Collector {
+    channels: Channels,
+    transport: Transport,
+    sink: Sink,
+    helix: HelixCredentials,
+    refresh_interval: Option<Duration>,
+    client: ClientConfig,
+    tracker: ChannelTracker,
+}
+
+impl Collector {
+    pub fn builder() -> CollectorBuilder {
+        CollectorBuilder::default()
+    }
+
+    ///A handle to the state of every joined channel, which can be queried while the collector
+    ///runs
+    pub fn channel_states(&self) -> ChannelTracker {
+        self.tracker.clone()
+    }
+
+    ///Runs until the transport stops receiving messages
+    pub async fn run(self) -> Result<(), MyError> {
+        let (sender, writer): (RecordSender, _) = match self.sink {
+            Sink::Db {
+                database_url,
+                batch_size,
+            } => {
+                let (sender, writer) = DB::spawn(DB::establish(&database_url)?, batch_size);
+                (sender.into(), Some(writer))
+            }
+            Sink::Archive { config, batch_size } => {
+                let (sender, writer) = DB::spawn(Box::new(FileSink::new(config)?), batch_size);
+                (sender.into(), Some(writer))
+            }
+            Sink::Custom { sink, batch_size } => {
+                let (sender, writer) = DB::spawn(sink, batch_size);
+                (sender.into(), Some(writer))
+            }
+            Sink::Sender(s) => (s.into(), None),
+            Sink::Channel(s) => (s.into(), None),
+        };
+
+        if let Transport::Replay(config) = self.transport {
+            replay::replay_messages(config, sender).await?;
+            //the replay has finished once everything is written, not just parsed
+            if let Some(writer) = writer {
+                writer
+                    .join()
+                    .map_err(|_| MyError::Other("writing the replayed records failed".into()))?;
+            }
+            return Ok(());
+        }
+
+        let (chans, refresh) = match self.channels {
+            Channels::Top(max_channels) => {
+                let helix = Helix::new(&self.helix)?;
+                let raw_channels = channels::top_connections(&helix, max_channels).await;
+                let chans = channels::cleanup_channels(raw_channels, max_channels);
+                let refresh = self.refresh_interval.map(|interval| RefreshConfig {
+                    helix,
+                    max_channels,
+                    interval,
+                });
+                (chans, refresh)
+            }
+            Channels::List(list) => (list, None),
+        };
+
+        twitchclient::get_messages(chans, sender, self.client, refresh, self.tracker).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fake_tmi::FakeTmi;
+    use crate::state::ChannelState;
+    use crate::twitchclient::Capabilities;
+    use crate::types::samples;
+    use std::future::Future;
+
+    fn collector(server: &FakeTmi, channel: &str) -> (Collector, async_mpsc::Receiver<Record>) {
+        let (builder, records) = Collector::builder()
+            .channels(Channels::List(vec![channel.to_string()]))
+            .client(ClientConfig {
+                capabilities: Capabilities {
+                    commands: true,
+                    membership: true,
+                },
+                server: server.server(),
+                ..ClientConfig::default()
+            })
+            .channel(16);
+        (builder.build(), records)
+    }
+
+    ///Runs the test alongside the collector, which shouldn't stop first
+    async fn with_collector(collector: Collector, test: impl Future<Output = ()>) {
+        tokio::select! {
+            r = collector.run() => panic!("collector stopped: {:?}", r.err()),
+            _ = test => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn test_collects_from_server() {
+        let server = FakeTmi::start().await;
+        let (collector, mut records) = collector(&server, "ronni");
+        let states = collector.channel_states();
+        with_collector(collector, async {
+            let id = server.joined("#ronni").await;
+            server.send(id, samples::PRIVMSG);
+            server.send(id, samples::USERNOTICE);
+            server.send(id, ":viewer!viewer@viewer.tmi.twitch.tv JOIN #ronni");
+
+            assert_eq!(
+                records.recv().await,
+                Some(Record::Message(samples::message()))
+            );
+            assert!(matches!(records.recv().await, Some(Record::UserNotice(_))));
+            assert!(
+                matches!(records.recv().await, Some(Record::Presence(p)) if p.login == "viewer")
+            );
+            assert_eq!(states.state("ronni"), Some(ChannelState::Joined));
+
+            let received = server.received();
+            for cap in &["tags", "commands", "membership"] {
+                assert!(received.contains(&format!("CAP REQ :twitch.tv/{}", cap)));
+            }
+            assert!(received.iter().any(|l| l.starts_with("NICK justinfan")));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_channels_are_lowercased() {
+        let server = FakeTmi::start().await;
+        let (collector, mut records) = collector(&server, "Ronni");
+        let states = collector.channel_states();
+        with_collector(collector, async {
+            let id = server.joined("#ronni").await;
+            //the echo matches the channel that was joined
+            let joined = async {
+                while states.state("ronni") != Some(ChannelState::Joined) {
+                    tokio::time::delay_for(Duration::from_millis(10)).await;
+                }
+            };
+            tokio::time::timeout(Duration::from_secs(10), joined)
+                .await
+                .expect("channel was never joined");
+            server.send(id, samples::PRIVMSG);
+            assert_eq!(
+                records.recv().await,
+                Some(Record::Message(samples::message()))
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_moves_channels() {
+        let server = FakeTmi::start().await;
+        let (collector, mut records) = collector(&server, "ronni");
+        with_collector(collector, async {
+            let old = server.joined("#ronni").await;
+            server.hold_joins();
+            server.reconnect(old);
+            server.held_joins(1).await;
+            //the old connection is kept until the new one's JOIN is echoed
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+            server.send(old, samples::PRIVMSG);
+            assert_eq!(
+                records.recv().await,
+                Some(Record::Message(samples::message()))
+            );
+
+            server.release_joins();
+            let new = server.rejoined("#ronni", old).await;
+            server.connection_count(1).await;
+            server.send(new, samples::REPLY);
+            assert_eq!(
+                records.recv().await,
+                Some(Record::Message(samples::reply()))
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_is_an_outage() {
+        let server = FakeTmi::start().await;
+        let (collector, mut records) = collector(&server, "ronni");
+        with_collector(collector, async {
+            let old = server.joined("#ronni").await;
+            server.disconnect(old);
+            let new = server.rejoined("#ronni", old).await;
+
+            match records.recv().await {
+                Some(Record::Outage(o)) => {
+                    assert_eq!(o.channel, "#ronni");
+                    assert_eq!(o.reason, "eof");
+                }
+                r => panic!("expected an outage, got {:?}", r),
+            }
+            server.send(new, samples::PRIVMSG);
+            assert_eq!(
+                records.recv().await,
+                Some(Record::Message(samples::message()))
+            );
+        })
+        .await;
+    }
+}
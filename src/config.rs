@@ -0,0 +1,336 @@
+use crate::archive::ArchiveConfig;
+use crate::channels::HelixCredentials;
+use crate::db::DEFAULT_BATCH_SIZE;
+use crate::error::MyError;
+use crate::pool::DEFAULT_CHANNELS_PER_CONNECTION;
+use crate::twitchclient::{Capabilities, IrcServer};
+use serde::Deserialize;
+use std::env;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+///Settings for a collector. Read from a toml file, then overridden by environment variables
+///(and by the command line in the binary).
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    ///Number of top channels to join. Ignored if `channels` is set.
+    pub max_channels: u64,
+    ///Explicit list of channels to join instead of the top channels
+    pub channels: Option<Vec<String>>,
+    pub database_url: Option<String>,
+    ///Write to files instead of the database
+    pub archive: Option<ArchiveConfig>,
+    pub batch_size: usize,
+    ///Seconds between refreshes of the joined channels
+    pub refresh_interval: u64,
+    ///Channels joined on each IRC connection before another is opened
+    pub channels_per_connection: usize,
+    pub capabilities: Capabilities,
+    pub helix: HelixCredentials,
+    pub irc: IrcServer,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_channels: 1000,
+            channels: None,
+            database_url: None,
+            archive: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+            refresh_interval: 30,
+            channels_per_connection: DEFAULT_CHANNELS_PER_CONNECTION,
+            capabilities: Capabilities::default(),
+            helix: HelixCredentials::default(),
+            irc: IrcServer::default(),
+        }
+    }
+}
+
+impl Config {
+    ///Reads the config file at `path`. If no path is given then `config.toml` is used if it
+    ///exists, otherwise the defaults.
+    pub fn from_file(path: Option<&Path>) -> Result<Config, MyError> {
+        let path = match path {
+            Some(p) => p,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => Path::new(DEFAULT_CONFIG_FILE),
+            None => return Ok(Config::default()),
+        };
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| MyError::Config(format!("could not read {}: {}", path.display(), e)))?;
+        Config::from_toml(&contents)
+            .map_err(|e| MyError::Config(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(contents)
+    }
+
+    ///Overrides settings with any that are set in the environment (or .env)
+    pub fn apply_env(&mut self) -> Result<(), MyError> {
+        dotenv::dotenv().ok();
+        if let Some(v) = env_parsed("MAX_CHANNELS")? {
+            self.max_channels = v;
+        }
+        if let Ok(v) = env::var("CHANNELS") {
+            self.channels = Some(split_channels(&v));
+        }
+        if let Ok(v) = env::var("DATABASE_URL") {
+            self.database_url = Some(v);
+        }
+        if let Ok(v) = env::var("ARCHIVE_DIRECTORY") {
+            self.archive_directory(PathBuf::from(v));
+        }
+        if let Some(v) = env_parsed("BATCH_SIZE")? {
+            self.batch_size = v;
+        }
+        if let Some(v) = env_parsed("REFRESH_INTERVAL")? {
+            self.refresh_interval = v;
+        }
+        if let Some(v) = env_parsed("CHANNELS_PER_CONNECTION")? {
+            self.channels_per_connection = v;
+        }
+        if let Some(v) = env_parsed("CAP_COMMANDS")? {
+            self.capabilities.commands = v;
+        }
+        if let Some(v) = env_parsed("CAP_MEMBERSHIP")? {
+            self.capabilities.membership = v;
+        }
+        if let Ok(v) = env::var("HELIX_CLIENT_ID") {
+            self.helix.client_id = v;
+        }
+        if let Ok(v) = env::var("HELIX_TOKEN") {
+            self.helix.token = Some(v);
+        }
+        if let Ok(v) = env::var("IRC_ADDRESS") {
+            self.irc.address = v;
+        }
+        if let Some(v) = env_parsed("IRC_TLS")? {
+            self.irc.tls = v;
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), MyError> {
+        match &self.channels {
+            Some(c) if c.is_empty() => return config_err("channels must not be empty"),
+            Some(c)
+                if c.iter()
+                    .any(|c| c.is_empty() || c.contains(char::is_whitespace)) =>
+            {
+                return config_err("channel names must be non-empty and contain no whitespace")
+            }
+            Some(_) => {}
+            None if self.max_channels == 0 => return config_err("max_channels must be at least 1"),
+            None => {}
+        }
+        if self.database_url.is_none() && self.archive.is_none() {
+            return config_err("one of database_url or archive must be set");
+        }
+        if self.archive.as_ref().and_then(|a| a.max_file_size) == Some(0) {
+            return config_err("archive max_file_size must be at least 1 byte");
+        }
+        if self.batch_size == 0 {
+            return config_err("batch_size must be at least 1");
+        }
+        if self.refresh_interval == 0 {
+            return config_err("refresh_interval must be at least 1 second");
+        }
+        if self.channels_per_connection == 0 {
+            return config_err("channels_per_connection must be at least 1");
+        }
+        if self.helix.client_id.is_empty() {
+            return config_err("helix client_id must not be empty");
+        }
+        if self.irc.address.is_empty() {
+            return config_err("irc address must not be empty");
+        }
+        Ok(())
+    }
+
+    ///Archives to `directory`, keeping any other archive settings
+    pub fn archive_directory(&mut self, directory: PathBuf) {
+        self.archive
+            .get_or_insert_with(ArchiveConfig::default)
+            .directory = directory;
+    }
+}
+
+///Parses a comma separated list of channels
+pub fn split_channels(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|c| c.trim().to_lowercase())
+        .filter(|c| !c.is_empty())
+        .collect()
+}
+
+fn env_parsed<T>(var: &str) -> Result<Option<T>, MyError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env::var(var) {
+        Ok(v) => v
+            .parse()
+            .map(Some)
+            .map_err(|e| MyError::Config(format!("{}={}: {}", var, v, e))),
+        Err(_) => Ok(None),
+    }
+}
+
+fn config_err(msg: &str) -> Result<(), MyError> {
+    Err(MyError::Config(msg.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::archive::Compression;
+
+    fn valid() -> Config {
+        Config {
+            database_url: Some("db.sqlite".to_string()),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_full_file() {
+        let config = Config::from_toml(
+            r#"
+            max_channels = 50
+            channels = ["a", "b"]
+            database_url = "test.sqlite"
+            batch_size = 10
+            refresh_interval = 60
+            channels_per_connection = 20
+
+            [archive]
+            directory = "/data/chat"
+            compression = "gzip"
+            max_file_size = 1000000
+
+            [capabilities]
+            commands = false
+            membership = true
+
+            [helix]
+            client_id = "abc"
+            token = "def"
+
+            [irc]
+            address = "localhost:6667"
+            tls = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                max_channels: 50,
+                channels: Some(vec!["a".to_string(), "b".to_string()]),
+                database_url: Some("test.sqlite".to_string()),
+                archive: Some(ArchiveConfig {
+                    directory: PathBuf::from("/data/chat"),
+                    compression: Compression::Gzip,
+                    rotate_hourly: true,
+                    max_file_size: Some(1000000),
+                }),
+                batch_size: 10,
+                refresh_interval: 60,
+                channels_per_connection: 20,
+                capabilities: Capabilities {
+                    commands: false,
+                    membership: true,
+                },
+                helix: HelixCredentials {
+                    client_id: "abc".to_string(),
+                    token: Some("def".to_string()),
+                },
+                irc: IrcServer {
+                    address: "localhost:6667".to_string(),
+                    tls: false,
+                },
+            }
+        );
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_missing_fields_use_defaults() {
+        let config = Config::from_toml("database_url = \"db.sqlite\"").unwrap();
+        assert_eq!(config, valid());
+    }
+
+    #[test]
+    fn test_unknown_field_rejected() {
+        assert!(Config::from_toml("max_chanels = 5").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        valid().validate().unwrap();
+        assert!(Config::default().validate().is_err());
+
+        let invalid = vec![
+            Config {
+                max_channels: 0,
+                ..valid()
+            },
+            Config {
+                channels: Some(vec![]),
+                ..valid()
+            },
+            Config {
+                channels: Some(vec!["a b".to_string()]),
+                ..valid()
+            },
+            Config {
+                batch_size: 0,
+                ..valid()
+            },
+            Config {
+                refresh_interval: 0,
+                ..valid()
+            },
+            Config {
+                channels_per_connection: 0,
+                ..valid()
+            },
+            Config {
+                irc: IrcServer {
+                    address: String::new(),
+                    tls: true,
+                },
+                ..valid()
+            },
+            Config {
+                archive: Some(ArchiveConfig {
+                    max_file_size: Some(0),
+                    ..ArchiveConfig::default()
+                }),
+                ..valid()
+            },
+        ];
+        for c in invalid {
+            assert!(c.validate().is_err(), "{:?} should be invalid", c);
+        }
+    }
+
+    #[test]
+    fn test_archive_instead_of_database() {
+        let mut config = Config::default();
+        config.archive_directory(PathBuf::from("chat"));
+        config.validate().unwrap();
+        assert_eq!(config.archive.unwrap().compression, Compression::Zstd);
+    }
+
+    #[test]
+    fn test_split_channels() {
+        assert_eq!(split_channels(" Foo,bar,, baz "), vec!["foo", "bar", "baz"]);
+    }
+}
@@ -1,58 +1,108 @@
 use crate::error::MyError;
-use crate::models::Message;
-use crate::schema::messages;
-use crate::types::TwitchMessage;
+use crate::models::{
+    uuid_to_bytes, Message, NewClearChat, NewClearMsg, NewMessageBadge, NewMessageEmote,
+    NewMessageReply, NewOutage, NewPresence, NewRoomState, NewUserNotice,
+};
+use crate::postgres::PgSink;
+use crate::schema::{
+    clear_chats, clear_msgs, message_badges, message_emotes, message_replies, messages, outages,
+    presence, room_states, user_notices,
+};
+use crate::types::{
+    Outage, Presence, Record, TwitchClearChat, TwitchClearMsg, TwitchMessage, TwitchRoomState,
+    TwitchUserNotice,
+};
 use chrono::Utc;
 use diesel::prelude::*;
-use dotenv::dotenv;
-use std::env;
 use std::sync::mpsc;
+use std::thread::JoinHandle;
 //TODO - handle errors better in this module
 
-const BATCH_SIZE: usize = 1024;
-//wrapper over db connection to batch insert messages and make code a bit cleaner. Also allows
+pub const DEFAULT_BATCH_SIZE: usize = 1024;
+
+///Somewhere records can be written to in batches, eg - a database
+pub trait MessageSink: Send {
+    ///Returns the number of records written
+    fn write(&mut self, records: Vec<Record>) -> Result<usize, MyError>;
+}
+
+//wrapper over a sink to batch insert messages and make code a bit cleaner. Also allows
 //easier use of database while program is running since batching means the db isn't constantly
 //locked.
-//TODO - try to make this less database backend dependant
 pub struct DB {
-    conn: SqliteConnection,
-    queue: (mpsc::Sender<TwitchMessage>, mpsc::Receiver<TwitchMessage>),
+    sink: Box<dyn MessageSink>,
+    queue: mpsc::Receiver<Record>,
     //this used to use ArrayVec but there was an issue with stackoverflow on debug builds
-    batch: Vec<TwitchMessage>,
+    batch: Vec<Record>,
+    batch_size: usize,
 }
 
 impl DB {
-    fn new() -> Result<DB, MyError> {
-        dotenv().ok();
-        let database_url = env::var("DATABASE_URL")?;
-        let conn = SqliteConnection::establish(&database_url)?;
-        let ret = DB {
-            conn,
-            queue: mpsc::channel::<TwitchMessage>(),
-            batch: Vec::with_capacity(BATCH_SIZE),
+    fn new(sink: Box<dyn MessageSink>, batch_size: usize) -> (DB, mpsc::Sender<Record>) {
+        let (sender, queue) = mpsc::channel::<Record>();
+        let datab = DB {
+            sink,
+            queue,
+            batch: Vec::with_capacity(batch_size),
+            batch_size,
         };
-        Ok(ret)
+        (datab, sender)
     }
 
+    ///Connects to a postgres database if the url starts with postgres:// or postgresql://,
+    ///otherwise treats it as a sqlite database
     //should this be a method on the db instead and multiple calls just clones the sender?
-    pub fn connection() -> Result<mpsc::Sender<TwitchMessage>, MyError> {
-        let mut datab: DB = DB::new()?;
-        let sender = datab.queue.0.clone();
-        std::thread::spawn(move || {
+    pub fn connection(
+        database_url: &str,
+        batch_size: usize,
+    ) -> Result<mpsc::Sender<Record>, MyError> {
+        Ok(DB::with_sink(DB::establish(database_url)?, batch_size))
+    }
+
+    pub(crate) fn establish(database_url: &str) -> Result<Box<dyn MessageSink>, MyError> {
+        Ok(if is_postgres_url(database_url) {
+            Box::new(PgSink::establish(database_url)?)
+        } else {
+            Box::new(SqliteSink::establish(database_url)?)
+        })
+    }
+
+    ///Batches records sent to the returned sender and writes them to `sink` on another thread
+    pub fn with_sink(sink: Box<dyn MessageSink>, batch_size: usize) -> mpsc::Sender<Record> {
+        DB::spawn(sink, batch_size).0
+    }
+
+    ///Like `with_sink`, also returning the writer thread, which finishes once every sender is
+    ///dropped and the last batch is written
+    pub(crate) fn spawn(
+        sink: Box<dyn MessageSink>,
+        batch_size: usize,
+    ) -> (mpsc::Sender<Record>, JoinHandle<()>) {
+        //the db doesn't keep a sender, so it stops once the caller's are dropped
+        let (mut datab, sender) = DB::new(sink, batch_size);
+        let writer = std::thread::spawn(move || {
             datab.run();
         });
-        Ok(sender)
+        (sender, writer)
     }
+
     //TODO - this should panic if things are very broken eg - database disappears
     fn run(&mut self) {
         let mut nr = 0;
-        while let Ok(v) = self.queue.1.recv() {
-            self.batch.push(v);
-            if self.batch.len() >= BATCH_SIZE {
+        while let Ok(record) = self.queue.recv() {
+            if let Record::Outage(_) = record {
+                //rare enough that they don't need batching
+                if let Err(e) = self.sink.write(vec![record]) {
+                    eprintln!("[{}] error inserting outage {:?}", Utc::now(), e);
+                }
+                continue;
+            }
+            self.batch.push(record);
+            if self.batch.len() >= self.batch_size {
                 match self.flush() {
                     Ok(num) => {
                         nr += num;
-                        println!("[{}] messages inserted: {}", Utc::now(), nr);
+                        println!("[{}] records inserted: {}", Utc::now(), nr);
                     }
                     Err(e) => {
                         eprintln!("[{}] error flushing to db {:?}", Utc::now(), e);
@@ -62,30 +112,608 @@ impl DB {
         }
     }
 
-    //without assert can just be inlined or potentially some error handling. I just need tests I
-    //think.
     pub fn flush(&mut self) -> Result<usize, MyError> {
-        match self.insert() {
-            Ok(num) => {
-                debug_assert!(self.batch.is_empty());
-                Ok(num)
+        let batch = std::mem::replace(&mut self.batch, Vec::with_capacity(self.batch_size));
+        self.sink.write(batch)
+    }
+}
+
+impl Drop for DB {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("[{}] error flushing to db {:?}", Utc::now(), e);
+        }
+    }
+}
+
+pub fn is_postgres_url(database_url: &str) -> bool {
+    database_url.starts_with("postgres://") || database_url.starts_with("postgresql://")
+}
+
+///A batch of records split up by the table they're stored in
+#[derive(Default)]
+pub(crate) struct Tables {
+    pub messages: Vec<TwitchMessage>,
+    pub user_notices: Vec<TwitchUserNotice>,
+    pub clear_chats: Vec<TwitchClearChat>,
+    pub clear_msgs: Vec<TwitchClearMsg>,
+    pub room_states: Vec<TwitchRoomState>,
+    pub presence: Vec<Presence>,
+    pub outages: Vec<Outage>,
+}
+
+impl From<Vec<Record>> for Tables {
+    fn from(records: Vec<Record>) -> Self {
+        let mut tables = Tables::default();
+        for record in records {
+            match record {
+                Record::Message(m) => tables.messages.push(m),
+                Record::UserNotice(n) => tables.user_notices.push(n),
+                Record::ClearChat(c) => tables.clear_chats.push(c),
+                Record::ClearMsg(c) => tables.clear_msgs.push(c),
+                Record::RoomState(r) => tables.room_states.push(r),
+                Record::Presence(p) => tables.presence.push(p),
+                Record::Outage(o) => tables.outages.push(o),
             }
-            Err(e) => Err(MyError::Db(Box::new(e))),
         }
+        tables
     }
+}
+
+///Writes to the tables created by the migrations in migrations/
+pub struct SqliteSink {
+    conn: SqliteConnection,
+}
 
-    fn insert(&mut self) -> QueryResult<usize> {
-        let records: Vec<Message> = self.batch.drain(..).map(Message::from).collect();
+impl SqliteSink {
+    pub fn establish(database_url: &str) -> Result<SqliteSink, MyError> {
+        Ok(SqliteSink {
+            conn: SqliteConnection::establish(database_url)?,
+        })
+    }
+}
 
-        diesel::insert_into(messages::table)
-            .values(records)
-            .execute(&self.conn)
+impl MessageSink for SqliteSink {
+    ///Inserts each type of record into its own table
+    fn write(&mut self, records: Vec<Record>) -> Result<usize, MyError> {
+        let tables = Tables::from(records);
+        let conn = &self.conn;
+        let inserted = conn.transaction::<_, diesel::result::Error, _>(|| {
+            //not counted since they're part of the messages
+            insert_message_children(conn, &tables.messages)?;
+            //messages and notices can be seen on two connections while moving channels, so ones
+            //that are already stored are skipped rather than failing the batch
+            Ok(diesel::insert_or_ignore_into(messages::table)
+                .values(into_vec::<_, Message>(tables.messages))
+                .execute(conn)?
+                + diesel::insert_or_ignore_into(user_notices::table)
+                    .values(into_vec::<_, NewUserNotice>(tables.user_notices))
+                    .execute(conn)?
+                + diesel::insert_into(clear_chats::table)
+                    .values(into_vec::<_, NewClearChat>(tables.clear_chats))
+                    .execute(conn)?
+                + diesel::insert_into(clear_msgs::table)
+                    .values(into_vec::<_, NewClearMsg>(tables.clear_msgs))
+                    .execute(conn)?
+                + diesel::insert_into(room_states::table)
+                    .values(into_vec::<_, NewRoomState>(tables.room_states))
+                    .execute(conn)?
+                + diesel::insert_into(presence::table)
+                    .values(into_vec::<_, NewPresence>(tables.presence))
+                    .execute(conn)?
+                + diesel::insert_into(outages::table)
+                    .values(into_vec::<_, NewOutage>(tables.outages))
+                    .execute(conn)?)
+        })?;
+        Ok(inserted)
     }
 }
 
-//TODO - is this correct?
-impl Drop for DB {
-    fn drop(&mut self) {
-        self.flush().unwrap(); //look at result here?
+///Inserts the badges, emotes and reply of each message into their own tables
+fn insert_message_children(conn: &SqliteConnection, messages: &[TwitchMessage]) -> QueryResult<()> {
+    let badges: Vec<NewMessageBadge> = messages
+        .iter()
+        .flat_map(NewMessageBadge::from_message)
+        .collect();
+    let emotes: Vec<NewMessageEmote> = messages
+        .iter()
+        .flat_map(NewMessageEmote::from_message)
+        .collect();
+    let replies: Vec<NewMessageReply> = messages
+        .iter()
+        .filter_map(NewMessageReply::from_message)
+        .collect();
+    for chunk in badges.chunks(CHILD_ROWS_PER_INSERT) {
+        diesel::insert_or_ignore_into(message_badges::table)
+            .values(chunk)
+            .execute(conn)?;
+    }
+    for chunk in emotes.chunks(CHILD_ROWS_PER_INSERT) {
+        diesel::insert_or_ignore_into(message_emotes::table)
+            .values(chunk)
+            .execute(conn)?;
+    }
+    for chunk in replies.chunks(CHILD_ROWS_PER_INSERT) {
+        diesel::insert_or_ignore_into(message_replies::table)
+            .values(chunk)
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+///Replaces everything stored from the tags of messages that are already in the database, eg -
+///after parsing their raw lines again. Messages are matched by id.
+pub(crate) fn rewrite_messages(
+    conn: &SqliteConnection,
+    messages: Vec<TwitchMessage>,
+) -> QueryResult<()> {
+    let ids: Vec<Vec<u8>> = messages.iter().map(|m| uuid_to_bytes(m.tags.id)).collect();
+    for chunk in ids.chunks(CHILD_ROWS_PER_INSERT) {
+        diesel::delete(message_badges::table.filter(message_badges::message_id.eq_any(chunk)))
+            .execute(conn)?;
+        diesel::delete(message_emotes::table.filter(message_emotes::message_id.eq_any(chunk)))
+            .execute(conn)?;
+        diesel::delete(message_replies::table.filter(message_replies::message_id.eq_any(chunk)))
+            .execute(conn)?;
+    }
+    insert_message_children(conn, &messages)?;
+    for message in messages {
+        let message = Message::from(message);
+        diesel::update(messages::table.find(&message.id))
+            .set(&message)
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+///Messages can have a lot of emotes, this keeps postgres inserts under its limit of 65535 bind
+///parameters. diesel inserts sqlite batches a row at a time, but the deletes when rewriting
+///messages still need to stay under sqlite's limit.
+pub(crate) const CHILD_ROWS_PER_INSERT: usize = 2048;
+
+pub(crate) fn into_vec<T, U: From<T>>(v: Vec<T>) -> Vec<U> {
+    v.into_iter().map(U::from).collect()
+}
+
+///Runs every up.sql in a migrations directory in order
+#[cfg(test)]
+pub(crate) fn run_migrations<C: Connection>(conn: &C, dir: &str) {
+    run_migrations_in(conn, dir, ..);
+}
+
+///Runs the up.sql of migrations with versions in a range, eg - to test migrating existing rows
+#[cfg(test)]
+pub(crate) fn run_migrations_in<'a, C: Connection, R: std::ops::RangeBounds<&'a str>>(
+    conn: &C,
+    dir: &str,
+    versions: R,
+) {
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(dir);
+    let mut migrations: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.is_dir() && versions.contains(&p.file_name().unwrap().to_str().unwrap()))
+        .collect();
+    migrations.sort();
+    for m in migrations {
+        let sql = std::fs::read_to_string(m.join("up.sql")).unwrap();
+        conn.batch_execute(&sql)
+            .unwrap_or_else(|e| panic!("{}: {}", m.display(), e));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::samples;
+    use diesel::connection::SimpleConnection;
+    use diesel::dsl::count_star;
+    use diesel::sql_types::{Bool, Integer, Text};
+
+    #[test]
+    fn test_sqlite_sink_writes_every_table() {
+        let mut sink = SqliteSink::establish(":memory:").unwrap();
+        run_migrations(&sink.conn, "migrations");
+        assert_eq!(sink.write(samples::records()).unwrap(), 7);
+
+        let conn = &sink.conn;
+        let count = |n: QueryResult<i64>| n.unwrap();
+        assert_eq!(count(messages::table.select(count_star()).first(conn)), 1);
+        assert_eq!(
+            count(user_notices::table.select(count_star()).first(conn)),
+            1
+        );
+        assert_eq!(
+            count(clear_chats::table.select(count_star()).first(conn)),
+            1
+        );
+        assert_eq!(count(clear_msgs::table.select(count_star()).first(conn)), 1);
+        assert_eq!(
+            count(room_states::table.select(count_star()).first(conn)),
+            1
+        );
+        assert_eq!(count(presence::table.select(count_star()).first(conn)), 1);
+        assert_eq!(count(outages::table.select(count_star()).first(conn)), 1);
+        let tenure: (Option<String>, Option<i32>) = messages::table
+            .select((messages::badge_info, messages::subscription_months))
+            .first(conn)
+            .unwrap();
+        assert_eq!(tenure, (Some("subscriber/8".to_string()), Some(8)));
+        let raw: String = messages::table
+            .select(messages::raw_message)
+            .first(conn)
+            .unwrap();
+        assert_eq!(format!("{}\r\n", raw), samples::PRIVMSG);
+        assert_eq!(
+            count(message_badges::table.select(count_star()).first(conn)),
+            2
+        );
+        let emotes: Vec<(String, i32, i32, String)> = message_emotes::table
+            .select((
+                message_emotes::emote_id,
+                message_emotes::start_index,
+                message_emotes::end_index,
+                message_emotes::name,
+            ))
+            .order(message_emotes::start_index)
+            .load(conn)
+            .unwrap();
+        assert_eq!(
+            emotes,
+            vec![
+                ("25".to_string(), 0, 4, "Kappa".to_string()),
+                ("1902".to_string(), 6, 10, "Keepo".to_string()),
+                ("25".to_string(), 12, 16, "Kappa".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reply_and_other_tags() {
+        let mut sink = SqliteSink::establish(":memory:").unwrap();
+        run_migrations(&sink.conn, "migrations");
+        let records = vec![
+            Record::Message(samples::message()),
+            Record::Message(samples::reply()),
+        ];
+        assert_eq!(sink.write(records).unwrap(), 2);
+
+        let conn = &sink.conn;
+        let parent: Vec<u8> = message_replies::table
+            .select(message_replies::parent_msg_id)
+            .first(conn)
+            .unwrap();
+        assert_eq!(parent, uuid_to_bytes(samples::message().tags.id));
+        let tags: Vec<(Option<bool>, Option<String>, Option<String>)> = messages::table
+            .select((messages::first_msg, messages::flags, messages::other_tags))
+            .order(messages::tmi_sent_ts)
+            .load(conn)
+            .unwrap();
+        assert_eq!(tags[0], (None, None, None));
+        assert_eq!(
+            tags[1],
+            (
+                Some(true),
+                Some("0-4:P.6/S.3,13-16:".to_string()),
+                Some(r#"{"pinned-chat-paid-amount":"500"}"#.to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn test_action_messages() {
+        let mut sink = SqliteSink::establish(":memory:").unwrap();
+        run_migrations(&sink.conn, "migrations");
+        assert_eq!(
+            sink.write(vec![Record::Message(samples::action())])
+                .unwrap(),
+            1
+        );
+
+        let conn = &sink.conn;
+        let stored: (String, bool) = messages::table
+            .select((messages::message, messages::action))
+            .first(conn)
+            .unwrap();
+        assert_eq!(stored, ("waves Kappa".to_string(), true));
+        let name: String = message_emotes::table
+            .select(message_emotes::name)
+            .first(conn)
+            .unwrap();
+        assert_eq!(name, "Kappa");
+    }
+
+    #[test]
+    fn test_action_messages_are_unwrapped_by_migration() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        run_migrations_in(&conn, "migrations", .."2026-10-18-190000");
+        conn.batch_execute(
+            "INSERT INTO messages (id, display_name, mod, room_id, tmi_sent_ts, user_id, channel, \
+             message, raw_message) VALUES \
+             (X'01', 'A', 0, 1, 0, '1', '#c', char(1) || 'ACTION waves Kappa' || char(1), 'raw'), \
+             (X'02', 'A', 0, 1, 0, '1', '#c', 'Kappa', 'raw'); \
+             INSERT INTO message_emotes VALUES (X'01', '25', 6, 10, 'ves K'), \
+             (X'02', '25', 0, 4, 'Kappa');",
+        )
+        .unwrap();
+        run_migrations_in(&conn, "migrations", "2026-10-18-190000"..);
+
+        let stored: Vec<(String, bool)> = messages::table
+            .select((messages::message, messages::action))
+            .order(messages::id)
+            .load(&conn)
+            .unwrap();
+        assert_eq!(
+            stored,
+            vec![
+                ("waves Kappa".to_string(), true),
+                ("Kappa".to_string(), false)
+            ]
+        );
+        let names: Vec<String> = message_emotes::table
+            .select(message_emotes::name)
+            .load(&conn)
+            .unwrap();
+        assert_eq!(names, vec!["Kappa", "Kappa"]);
+    }
+
+    #[test]
+    fn test_duplicates_are_skipped() {
+        let mut sink = SqliteSink::establish(":memory:").unwrap();
+        run_migrations(&sink.conn, "migrations");
+        let records = || {
+            vec![
+                Record::Message(samples::message()),
+                Record::Message(samples::message()),
+                Record::Message(samples::reply()),
+                samples::records().swap_remove(1),
+            ]
+        };
+        assert_eq!(sink.write(records()).unwrap(), 3);
+        assert_eq!(sink.write(records()).unwrap(), 0);
+
+        let conn = &sink.conn;
+        let count = |n: QueryResult<i64>| n.unwrap();
+        assert_eq!(count(messages::table.select(count_star()).first(conn)), 2);
+        assert_eq!(
+            count(user_notices::table.select(count_star()).first(conn)),
+            1
+        );
+        assert_eq!(
+            count(message_badges::table.select(count_star()).first(conn)),
+            3
+        );
+        assert_eq!(
+            count(message_emotes::table.select(count_star()).first(conn)),
+            3
+        );
+        assert_eq!(
+            count(message_replies::table.select(count_star()).first(conn)),
+            1
+        );
+    }
+
+    #[test]
+    fn test_batches_are_flushed_on_drop() {
+        let path = std::env::temp_dir().join(format!("flush-{}.sqlite", std::process::id()));
+        let url = path.to_str().unwrap();
+        let sink = SqliteSink::establish(url).unwrap();
+        run_migrations(&sink.conn, "migrations");
+        let (mut db, sender) = DB::new(Box::new(sink), 10);
+        sender.send(Record::Message(samples::message())).unwrap();
+        drop(sender);
+        db.run();
+
+        let conn = SqliteConnection::establish(url).unwrap();
+        let stored = || -> i64 { messages::table.select(count_star()).first(&conn).unwrap() };
+        //the batch isn't full so nothing is written until the db is dropped
+        assert_eq!(stored(), 0);
+        drop(db);
+        assert_eq!(stored(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    ///The type diesel print-schema would give a column from `pragma_table_info`
+    fn diesel_type(ty: &str, notnull: bool, pk: i32) -> String {
+        let ty = match ty {
+            "TEXT" => "Text",
+            "INTEGER" => "Integer",
+            "BIGINT" => "BigInt",
+            "BLOB" => "Binary",
+            "BOOLEAN" => "Bool",
+            "TIMESTAMP" | "DATETIME" => "Timestamp",
+            other => panic!("no diesel type for {}", other),
+        };
+        //integer primary keys are the rowid so can't be null
+        if notnull || (pk > 0 && ty == "Integer") {
+            ty.to_string()
+        } else {
+            format!("Nullable<{}>", ty)
+        }
+    }
+
+    ///(table, column, type) for every column declared in schema.rs, in table order
+    fn declared_columns() -> Vec<(String, String, String)> {
+        let mut columns = vec![];
+        let mut table = None;
+        let mut sql_name = None;
+        for line in include_str!("schema.rs").lines().map(str::trim) {
+            if let Some(name) = line.strip_prefix("#[sql_name = \"") {
+                sql_name = Some(name.trim_end_matches("\"]").to_string());
+            } else if line.ends_with(") {") {
+                table = line.split_whitespace().next().map(str::to_string);
+            } else if line == "}" {
+                table = None;
+            } else if let (Some(t), Some((name, ty))) = (&table, line.split_once(" -> ")) {
+                let name = sql_name.take().unwrap_or_else(|| name.to_string());
+                columns.push((t.clone(), name, ty.trim_end_matches(',').to_string()));
+            }
+        }
+        columns.sort_by(|a, b| a.0.cmp(&b.0));
+        columns
+    }
+
+    #[test]
+    fn test_schema_matches_migrations() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        run_migrations(&conn, "migrations");
+        let migrated: Vec<(String, String, String, bool, i32)> =
+            diesel::dsl::sql::<(Text, Text, Text, Bool, Integer)>(
+                "SELECT m.name, c.name, c.type, c.\"notnull\", c.pk \
+                 FROM sqlite_master m, pragma_table_info(m.name) c \
+                 WHERE m.type = 'table' AND m.name NOT LIKE 'sqlite_%' \
+                 AND m.name != 'messages_unmigrated' ORDER BY m.name, c.cid",
+            )
+            .load(&conn)
+            .unwrap();
+        let migrated: Vec<(String, String, String)> = migrated
+            .into_iter()
+            .map(|(table, name, ty, notnull, pk)| (table, name, diesel_type(&ty, notnull, pk)))
+            .collect();
+        assert_eq!(migrated, declared_columns());
+    }
+
+    #[test]
+    fn test_messages_are_migrated_to_strict_table() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        run_migrations_in(&conn, "migrations", .."2026-10-18-130000");
+        conn.batch_execute(
+            "INSERT INTO messages (id, display_name, mod, room_id, tmi_sent_ts, user_id, channel, \
+             message, raw_message) VALUES \
+             ('a', 'A', 0, 1, '2017-10-05T23:36:12.675+00:00', '1', '#c', 'hi', 'raw'), \
+             ('b', 'B', 1, 1, '2017-10-05T23:36:12+00:00', '1', '#c', 'hi', 'raw'), \
+             ('c', NULL, 1, 1, '2017-10-05T23:36:12+00:00', '1', '#c', 'hi', 'raw');",
+        )
+        .unwrap();
+        conn.batch_execute(include_str!(
+            "../migrations/2026-10-18-130000_strict_messages/up.sql"
+        ))
+        .unwrap();
+
+        //the format diesel uses for Timestamp columns
+        let migrated: Vec<(String, String)> =
+            diesel::dsl::sql::<(Text, Text)>("SELECT id, tmi_sent_ts FROM messages ORDER BY id")
+                .load(&conn)
+                .unwrap();
+        assert_eq!(migrated.len(), 2);
+        assert_eq!(migrated[0].1, "2017-10-05 23:36:12.675");
+        assert_eq!(migrated[1].1, "2017-10-05 23:36:12");
+        let unmigrated: i64 = diesel::dsl::sql("SELECT COUNT(*) FROM messages_unmigrated")
+            .get_result(&conn)
+            .unwrap();
+        assert_eq!(unmigrated, 1);
+    }
+
+    #[test]
+    fn test_messages_are_migrated_to_compact_ids_and_timestamps() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        run_migrations_in(&conn, "migrations", .."2026-10-18-140000");
+        conn.batch_execute(
+            "INSERT INTO messages (id, display_name, mod, room_id, tmi_sent_ts, user_id, channel, \
+             message, raw_message) VALUES \
+             ('b34ccfc7-4977-403a-8a94-33c6bac34fb8', 'A', 0, 1, '2017-10-05 23:36:12.675', \
+             '1', '#c', 'Kappa hi', 'raw'), \
+             ('not a uuid', 'B', 1, 1, '2017-10-05 23:36:12', '1', '#c', 'hi', 'raw'); \
+             UPDATE messages SET badge_info = 'founder/3', badges = '[\"founder/0\"]', \
+             emotes = '[\"25:0-4\"]' WHERE display_name = 'A'; \
+             INSERT INTO user_notices (id, channel, room_id, msg_id, params, tmi_sent_ts, \
+             raw_message) VALUES ('b34ccfc7-4977-403a-8a94-33c6bac34fb8', '#c', 1, 'sub', '{}', \
+             '2017-10-05T23:36:12.675+00:00', 'raw'); \
+             INSERT INTO clear_msgs (channel, target_msg_id, tmi_sent_ts, raw_message) VALUES \
+             ('#c', 'b34ccfc7-4977-403a-8a94-33c6bac34fb8', '2017-10-05T23:36:12.675+00:00', \
+             'raw'); \
+             INSERT INTO clear_chats (channel, room_id, tmi_sent_ts, raw_message) VALUES \
+             ('#c', 1, '2017-10-05T23:36:12+00:00', 'raw');",
+        )
+        .unwrap();
+        //the later migrations fill in badges, emotes and months from the converted rows
+        run_migrations_in(&conn, "migrations", "2026-10-18-140000"..);
+
+        let migrated: Vec<(Vec<u8>, i64)> = messages::table
+            .select((messages::id, messages::tmi_sent_ts))
+            .load(&conn)
+            .unwrap();
+        assert_eq!(migrated.len(), 1);
+        assert_eq!(
+            crate::models::uuid_from_bytes(&migrated[0].0).unwrap(),
+            samples::message().tags.id
+        );
+        assert_eq!(migrated[0].1, 1507246572675);
+        let months: Option<i32> = messages::table
+            .select(messages::subscription_months)
+            .first(&conn)
+            .unwrap();
+        assert_eq!(months, Some(3));
+        let badges: Vec<String> = message_badges::table
+            .select(message_badges::name)
+            .load(&conn)
+            .unwrap();
+        assert_eq!(badges, vec!["founder"]);
+        let emotes: Vec<String> = message_emotes::table
+            .select(message_emotes::name)
+            .load(&conn)
+            .unwrap();
+        assert_eq!(emotes, vec!["Kappa"]);
+        let unmigrated: i64 = diesel::dsl::sql("SELECT COUNT(*) FROM messages_unmigrated")
+            .get_result(&conn)
+            .unwrap();
+        assert_eq!(unmigrated, 1);
+
+        let id = uuid_to_bytes(samples::message().tags.id);
+        let notice: (Vec<u8>, i64) = user_notices::table
+            .select((user_notices::id, user_notices::tmi_sent_ts))
+            .first(&conn)
+            .unwrap();
+        assert_eq!(notice, (id.clone(), 1507246572675));
+        let clear: (Vec<u8>, i64) = clear_msgs::table
+            .select((clear_msgs::target_msg_id, clear_msgs::tmi_sent_ts))
+            .first(&conn)
+            .unwrap();
+        assert_eq!(clear, (id, 1507246572675));
+        let cleared: i64 = clear_chats::table
+            .select(clear_chats::tmi_sent_ts)
+            .first(&conn)
+            .unwrap();
+        assert_eq!(cleared, 1507246572000);
+    }
+
+    #[test]
+    fn test_collected_timestamps_are_migrated_to_millis() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        run_migrations_in(&conn, "migrations", .."2026-10-18-200000");
+        conn.batch_execute(
+            "INSERT INTO outages (channel, started_at, ended_at, reason) VALUES \
+             ('#c', '2017-10-05T23:36:12.675+00:00', '2017-10-05T23:36:13.675123456+00:00', \
+             'closed'); \
+             INSERT INTO room_states (channel, room_id, received_at, raw_message) VALUES \
+             ('#c', 1, '2017-10-05T23:36:12.675+00:00', 'raw'); \
+             INSERT INTO presence (channel, login, event, received_at) VALUES \
+             ('#c', 'a', 'join', '2017-10-05T23:36:12.675+00:00');",
+        )
+        .unwrap();
+        run_migrations_in(&conn, "migrations", "2026-10-18-200000"..);
+
+        let outage: (i64, i64) = outages::table
+            .select((outages::started_at, outages::ended_at))
+            .first(&conn)
+            .unwrap();
+        assert_eq!(outage, (1507246572675, 1507246573675));
+        let state: i64 = room_states::table
+            .select(room_states::received_at)
+            .first(&conn)
+            .unwrap();
+        assert_eq!(state, 1507246572675);
+        let joined: i64 = presence::table
+            .select(presence::received_at)
+            .first(&conn)
+            .unwrap();
+        assert_eq!(joined, 1507246572675);
+    }
+
+    #[test]
+    fn test_postgres_url() {
+        assert!(is_postgres_url("postgres://user@localhost/chat"));
+        assert!(is_postgres_url("postgresql://localhost/chat"));
+        assert!(!is_postgres_url("db.sqlite"));
     }
 }
@@ -1,12 +1,29 @@
+use std::fmt;
+
 //TODO use failure crate?
 #[derive(Debug)]
 pub enum MyError {
     Db(Box<dyn std::error::Error>),
     Parse(&'static str),
     DotEnv(std::env::VarError),
+    Config(String),
     Other(Box<dyn std::error::Error>),
 }
 
+impl fmt::Display for MyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MyError::Db(e) => write!(f, "database error: {}", e),
+            MyError::Parse(s) => write!(f, "parse error: {}", s),
+            MyError::DotEnv(e) => write!(f, "environment error: {}", e),
+            MyError::Config(s) => write!(f, "invalid configuration: {}", s),
+            MyError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MyError {}
+
 impl From<std::env::VarError> for MyError {
     fn from(e: std::env::VarError) -> Self {
         MyError::DotEnv(e)
@@ -18,3 +35,15 @@ impl From<diesel::ConnectionError> for MyError {
         MyError::Db(Box::new(e))
     }
 }
+
+impl From<diesel::result::Error> for MyError {
+    fn from(e: diesel::result::Error) -> Self {
+        MyError::Db(Box::new(e))
+    }
+}
+
+impl From<std::io::Error> for MyError {
+    fn from(e: std::io::Error) -> Self {
+        MyError::Other(Box::new(e))
+    }
+}
@@ -0,0 +1,349 @@
+use crate::archive::sanitise;
+use crate::db::is_postgres_url;
+use crate::error::MyError;
+use crate::models::{timestamp_from_millis, uuid_from_bytes, Message};
+use arrow_array::builder::{ListBuilder, StringBuilder};
+use arrow_array::{
+    ArrayRef, BooleanArray, Int32Array, RecordBatch, StringArray, TimestampMillisecondArray,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::{DateTime, NaiveDate, Utc};
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Binary, Text};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
+
+//rows are loaded a page at a time since diesel reads whole results into memory
+const PAGE_SIZE: i64 = 10_000;
+
+macro_rules! page_query {
+    ($filter:expr) => {
+        concat!(
+            "SELECT id, badge_info, badges, bits, color, display_name, emotes, mod AS mod_, ",
+            "room_id, tmi_sent_ts, user_id, channel, message, raw_message, subscription_months, ",
+            "client_nonce, emote_only, first_msg, flags, returning_chatter, subscriber, turbo, ",
+            "user_type, vip, other_tags, action FROM messages ",
+            $filter,
+            " ORDER BY channel, tmi_sent_ts, id LIMIT ?"
+        )
+    };
+}
+
+///Writes the messages table of a sqlite database to parquet files, one per channel per day, at
+///`directory/channel/yyyy-mm-dd.parquet`. Existing files are overwritten. Returns the number of
+///messages written.
+pub fn export_messages(database_url: &str, directory: &Path) -> Result<usize, MyError> {
+    if is_postgres_url(database_url) {
+        return Err(MyError::Config(
+            "parquet export only supports sqlite databases".to_string(),
+        ));
+    }
+    let conn = SqliteConnection::establish(database_url)?;
+    export(&conn, directory)
+}
+
+fn export(conn: &SqliteConnection, directory: &Path) -> Result<usize, MyError> {
+    let schema = schema();
+    let mut current: Option<Partition> = None;
+    let mut exported = 0;
+    //keyset pagination, starting after the last row of the previous page
+    let mut last: Option<(String, i64, Vec<u8>)> = None;
+    loop {
+        let page: Vec<Message> = match &last {
+            None => diesel::sql_query(page_query!(""))
+                .bind::<BigInt, _>(PAGE_SIZE)
+                .load(conn)?,
+            Some((channel, tmi_sent_ts, id)) => {
+                diesel::sql_query(page_query!("WHERE (channel, tmi_sent_ts, id) > (?, ?, ?)"))
+                    .bind::<Text, _>(channel)
+                    .bind::<BigInt, _>(tmi_sent_ts)
+                    .bind::<Binary, _>(id)
+                    .bind::<BigInt, _>(PAGE_SIZE)
+                    .load(conn)?
+            }
+        };
+        let done = (page.len() as i64) < PAGE_SIZE;
+        if let Some(m) = page.last() {
+            last = Some((m.channel.clone(), m.tmi_sent_ts, m.id.clone()));
+        }
+        let rows = page
+            .into_iter()
+            .map(Row::try_from)
+            .collect::<Result<Vec<Row>, MyError>>()?;
+        for chunk in rows.chunk_by(|a, b| a.key() == b.key()) {
+            let key = chunk[0].key();
+            if current.as_ref().map(|p| &p.key) != Some(&key) {
+                if let Some(partition) = current.take() {
+                    partition.close()?;
+                }
+                current = Some(Partition::create(directory, key, schema.clone())?);
+            }
+            let partition = current.as_mut().unwrap();
+            partition
+                .writer
+                .write(&batch(&schema, chunk)?)
+                .map_err(other)?;
+            exported += chunk.len();
+        }
+        if done {
+            break;
+        }
+        println!("[{}] messages exported: {}", Utc::now(), exported);
+    }
+    if let Some(partition) = current {
+        partition.close()?;
+    }
+    Ok(exported)
+}
+
+fn schema() -> SchemaRef {
+    let list = || DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)));
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("badge_info", DataType::Utf8, true),
+        Field::new("badges", list(), true),
+        Field::new("bits", DataType::Int32, true),
+        Field::new("color", DataType::Utf8, true),
+        Field::new("display_name", DataType::Utf8, false),
+        Field::new("emotes", list(), true),
+        Field::new("mod", DataType::Boolean, true),
+        Field::new("room_id", DataType::Int32, false),
+        Field::new(
+            "tmi_sent_ts",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("user_id", DataType::Utf8, false),
+        Field::new("channel", DataType::Utf8, false),
+        Field::new("message", DataType::Utf8, false),
+        Field::new("raw_message", DataType::Utf8, false),
+        Field::new("subscription_months", DataType::Int32, true),
+        Field::new("client_nonce", DataType::Utf8, true),
+        Field::new("emote_only", DataType::Boolean, true),
+        Field::new("first_msg", DataType::Boolean, true),
+        Field::new("flags", DataType::Utf8, true),
+        Field::new("returning_chatter", DataType::Boolean, true),
+        Field::new("subscriber", DataType::Boolean, true),
+        Field::new("turbo", DataType::Boolean, true),
+        Field::new("user_type", DataType::Utf8, true),
+        Field::new("vip", DataType::Boolean, true),
+        Field::new("other_tags", DataType::Utf8, true),
+        Field::new("action", DataType::Boolean, false),
+    ]))
+}
+
+///A message with the id, timestamp and json columns parsed
+struct Row {
+    message: Message,
+    id: Uuid,
+    badges: Option<Vec<String>>,
+    emotes: Option<Vec<String>>,
+    tmi_sent_ts: DateTime<Utc>,
+}
+
+impl Row {
+    fn key(&self) -> (String, NaiveDate) {
+        (self.message.channel.clone(), self.tmi_sent_ts.date_naive())
+    }
+}
+
+impl TryFrom<Message> for Row {
+    type Error = MyError;
+
+    fn try_from(message: Message) -> Result<Self, Self::Error> {
+        let list = |json: &Option<String>| -> Result<Option<Vec<String>>, MyError> {
+            json.as_deref()
+                .map(serde_json::from_str)
+                .transpose()
+                .map_err(|_| MyError::Parse("badges or emotes json"))
+        };
+        Ok(Row {
+            badges: list(&message.badges)?,
+            emotes: list(&message.emotes)?,
+            id: uuid_from_bytes(&message.id)?,
+            tmi_sent_ts: timestamp_from_millis(message.tmi_sent_ts)?,
+            message,
+        })
+    }
+}
+
+fn batch(schema: &SchemaRef, rows: &[Row]) -> Result<RecordBatch, MyError> {
+    let strings = |f: fn(&Message) -> Option<&str>| -> ArrayRef {
+        Arc::new(rows.iter().map(|r| f(&r.message)).collect::<StringArray>())
+    };
+    let bools = |f: fn(&Message) -> Option<bool>| -> ArrayRef {
+        Arc::new(rows.iter().map(|r| f(&r.message)).collect::<BooleanArray>())
+    };
+    let lists = |f: fn(&Row) -> &Option<Vec<String>>| -> ArrayRef {
+        let mut builder = ListBuilder::new(StringBuilder::new());
+        for row in rows {
+            match f(row) {
+                Some(values) => {
+                    for v in values {
+                        builder.values().append_value(v);
+                    }
+                    builder.append(true);
+                }
+                None => builder.append(false),
+            }
+        }
+        Arc::new(builder.finish())
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            rows.iter()
+                .map(|r| Some(r.id.to_string()))
+                .collect::<StringArray>(),
+        ),
+        strings(|m| m.badge_info.as_deref()),
+        lists(|r| &r.badges),
+        Arc::new(rows.iter().map(|r| r.message.bits).collect::<Int32Array>()),
+        strings(|m| m.color.as_deref()),
+        strings(|m| Some(&m.display_name)),
+        lists(|r| &r.emotes),
+        bools(|m| m.mod_),
+        Arc::new(
+            rows.iter()
+                .map(|r| Some(r.message.room_id))
+                .collect::<Int32Array>(),
+        ),
+        Arc::new(
+            rows.iter()
+                .map(|r| Some(r.tmi_sent_ts.timestamp_millis()))
+                .collect::<TimestampMillisecondArray>()
+                .with_timezone("UTC"),
+        ),
+        strings(|m| Some(&m.user_id)),
+        strings(|m| Some(&m.channel)),
+        strings(|m| Some(&m.message)),
+        strings(|m| Some(&m.raw_message)),
+        Arc::new(
+            rows.iter()
+                .map(|r| r.message.subscription_months)
+                .collect::<Int32Array>(),
+        ),
+        strings(|m| m.client_nonce.as_deref()),
+        bools(|m| m.emote_only),
+        bools(|m| m.first_msg),
+        strings(|m| m.flags.as_deref()),
+        bools(|m| m.returning_chatter),
+        bools(|m| m.subscriber),
+        bools(|m| m.turbo),
+        strings(|m| m.user_type.as_deref()),
+        bools(|m| m.vip),
+        strings(|m| m.other_tags.as_deref()),
+        bools(|m| Some(m.action)),
+    ];
+    RecordBatch::try_new(schema.clone(), columns).map_err(other)
+}
+
+///The file for one channel's messages on one day
+struct Partition {
+    key: (String, NaiveDate),
+    writer: ArrowWriter<File>,
+}
+
+impl Partition {
+    fn create(
+        directory: &Path,
+        key: (String, NaiveDate),
+        schema: SchemaRef,
+    ) -> Result<Partition, MyError> {
+        let path = partition_path(directory, &key.0, key.1);
+        fs::create_dir_all(path.parent().unwrap())?;
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let writer =
+            ArrowWriter::try_new(File::create(&path)?, schema, Some(props)).map_err(other)?;
+        Ok(Partition { key, writer })
+    }
+
+    fn close(self) -> Result<(), MyError> {
+        self.writer.close().map_err(other)?;
+        Ok(())
+    }
+}
+
+fn partition_path(directory: &Path, channel: &str, day: NaiveDate) -> PathBuf {
+    directory
+        .join(sanitise(channel))
+        .join(format!("{}.parquet", day.format("%Y-%m-%d")))
+}
+
+fn other<E: std::error::Error + 'static>(e: E) -> MyError {
+    MyError::Other(Box::new(e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::run_migrations;
+    use crate::schema::messages;
+    use crate::types::samples;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Int32Type, TimestampMillisecondType};
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_export_partitions_by_channel_and_day() {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        run_migrations(&conn, "migrations");
+        let mut rows = vec![];
+        let rows_in = [("#ronni", 0), ("#ronni", 0), ("#ronni", 1), ("#bar", 0)];
+        for (i, (channel, days)) in rows_in.iter().enumerate() {
+            let mut message = samples::message();
+            message.tags.id = Uuid::from_bytes([i as u8; 16]);
+            message.tags.tmi_sent_ts += chrono::Duration::days(*days);
+            message.channel = channel.to_string();
+            rows.push(Message::from(message));
+        }
+        diesel::insert_into(messages::table)
+            .values(&rows)
+            .execute(&conn)
+            .unwrap();
+
+        let dir = std::env::temp_dir().join(format!("parquet-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(export(&conn, &dir).unwrap(), 4);
+
+        let day = NaiveDate::from_ymd_opt(2017, 10, 5).unwrap();
+        let read = |channel: &str, day: NaiveDate| -> Vec<RecordBatch> {
+            let file = File::open(partition_path(&dir, channel, day)).unwrap();
+            ParquetRecordBatchReaderBuilder::try_new(file)
+                .unwrap()
+                .build()
+                .unwrap()
+                .map(Result::unwrap)
+                .collect()
+        };
+        let batches = read("#ronni", day);
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).sum::<usize>(), 2);
+        assert_eq!(read("#ronni", day.succ_opt().unwrap())[0].num_rows(), 1);
+        assert_eq!(read("#bar", day)[0].num_rows(), 1);
+
+        let batch = &batches[0];
+        let ts = batch
+            .column_by_name("tmi_sent_ts")
+            .unwrap()
+            .as_primitive::<TimestampMillisecondType>();
+        assert_eq!(ts.value(0), 1507246572675);
+        let badges = batch.column_by_name("badges").unwrap().as_list::<i32>();
+        let first = badges.value(0);
+        assert_eq!(first.as_string::<i32>().value(0), "subscriber/6");
+        assert!(batch.column_by_name("bits").unwrap().is_null(0));
+        let months = batch
+            .column_by_name("subscription_months")
+            .unwrap()
+            .as_primitive::<Int32Type>();
+        assert_eq!(months.value(0), 8);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+//a stand in for the parts of the helix API the channel list comes from, for testing without going
+//online. Serves streams (with cursors) and users, sends rate limit headers and can be told to
+//fail requests.
+
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+const RATE_LIMIT: usize = 800;
+
+///A live channel
+#[derive(Debug, Clone)]
+pub(crate) struct FakeStream {
+    pub(crate) user_id: String,
+    pub(crate) login: String,
+}
+
+impl FakeStream {
+    pub(crate) fn new(user_id: &str, login: &str) -> FakeStream {
+        FakeStream {
+            user_id: user_id.to_string(),
+            login: login.to_string(),
+        }
+    }
+}
+
+///A request as the server saw it
+#[derive(Debug, Clone)]
+pub(crate) struct FakeRequest {
+    ///eg - streams
+    pub(crate) endpoint: String,
+    pub(crate) query: Vec<(String, String)>,
+    ///Lowercased names
+    pub(crate) headers: HashMap<String, String>,
+}
+
+impl FakeRequest {
+    pub(crate) fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Default)]
+struct State {
+    ///In viewer count order
+    streams: Vec<FakeStream>,
+    requests: Vec<FakeRequest>,
+    ///Responses to give instead of the real ones, by endpoint and first in first out
+    failures: Vec<(&'static str, u16, &'static str)>,
+}
+
+pub(crate) struct FakeHelix {
+    address: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeHelix {
+    pub(crate) async fn start(streams: Vec<FakeStream>) -> FakeHelix {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State {
+            streams,
+            ..State::default()
+        }));
+        let accepting = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, accepting.clone()));
+            }
+        });
+        FakeHelix { address, state }
+    }
+
+    ///To pass to `Helix::with_base_url`
+    pub(crate) fn url(&self) -> String {
+        format!("http://{}/helix/", self.address)
+    }
+
+    ///Answers the next request to `endpoint` with an error, eg - 401 "Invalid OAuth token"
+    pub(crate) fn fail_next(&self, endpoint: &'static str, status: u16, message: &'static str) {
+        let mut state = self.state.lock().unwrap();
+        state.failures.push((endpoint, status, message));
+    }
+
+    ///Answers the next request to `endpoint` with 429 Too Many Requests
+    pub(crate) fn rate_limit_next(&self, endpoint: &'static str) {
+        self.fail_next(endpoint, 429, "Too Many Requests");
+    }
+
+    pub(crate) fn requests(&self) -> Vec<FakeRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+async fn serve(stream: TcpStream, state: Arc<Mutex<State>>) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    //eg - GET /helix/streams?first=100 HTTP/1.1
+    let target = match lines.next_line().await {
+        Ok(Some(line)) => line.split(' ').nth(1).unwrap_or_default().to_string(),
+        _ => return,
+    };
+    let mut headers = HashMap::new();
+    while let Ok(Some(line)) = lines.next_line().await {
+        match line.split_once(':') {
+            Some((name, value)) => {
+                headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+            }
+            //GETs have no body so the request ends with the headers
+            None => break,
+        }
+    }
+    let url = reqwest::Url::parse(&format!("http://helix{}", target)).unwrap();
+    let request = FakeRequest {
+        endpoint: url.path().trim_start_matches("/helix/").to_string(),
+        query: url.query_pairs().into_owned().collect(),
+        headers,
+    };
+    let (status, body, remaining) = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(request.clone());
+        let remaining = RATE_LIMIT.saturating_sub(state.requests.len());
+        match respond(&mut state, &request) {
+            (429, body) => (429, body, 0),
+            (status, body) => (status, body, remaining),
+        }
+    };
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Ratelimit-Limit: {}\r\nRatelimit-Remaining: {}\r\nRatelimit-Reset: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        RATE_LIMIT,
+        remaining,
+        Utc::now().timestamp(),
+        body
+    );
+    let _ = write.write_all(response.as_bytes()).await;
+    let _ = write.shutdown().await;
+}
+
+fn respond(state: &mut State, request: &FakeRequest) -> (u16, Value) {
+    let failure = state
+        .failures
+        .iter()
+        .position(|(endpoint, _, _)| *endpoint == request.endpoint);
+    if let Some(i) = failure {
+        let (_, status, message) = state.failures.remove(i);
+        return error(status, message);
+    }
+    if !request.headers.contains_key("client-id") {
+        return error(401, "Client ID is missing");
+    }
+    match request.endpoint.as_str() {
+        "streams" => streams(&state.streams, request),
+        "users" => users(&state.streams, request),
+        _ => error(404, "Not Found"),
+    }
+}
+
+fn streams(streams: &[FakeStream], request: &FakeRequest) -> (u16, Value) {
+    let first = match request.param("first").map(str::parse::<usize>) {
+        None => 20,
+        Some(Ok(n)) if (1..=100).contains(&n) => n,
+        Some(_) => return error(400, "first must be between 1 and 100"),
+    };
+    //the cursor is just the index of the next stream
+    let start = match request.param("after").map(str::parse::<usize>) {
+        None => 0,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => return error(400, "Invalid cursor"),
+    };
+    let end = std::cmp::min(start + first, streams.len());
+    let data: Vec<Value> = streams
+        .get(start..end)
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            json!({
+                "community_ids": [],
+                "game_id": "509658",
+                "id": format!("{}", 1000 + start + i),
+                "language": "en",
+                "started_at": "2026-10-18T00:00:00Z",
+                "tag_ids": [],
+                "thumbnail_url": "",
+                "title": "",
+                "type": "live",
+                "user_id": s.user_id,
+                "user_name": s.login,
+                "viewer_count": streams.len() - start - i,
+            })
+        })
+        .collect();
+    let pagination = if end < streams.len() {
+        json!({ "cursor": end.to_string() })
+    } else {
+        json!({})
+    };
+    (200, json!({ "data": data, "pagination": pagination }))
+}
+
+fn users(streams: &[FakeStream], request: &FakeRequest) -> (u16, Value) {
+    let ids: Vec<&str> = request
+        .query
+        .iter()
+        .filter(|(k, _)| k == "id")
+        .map(|(_, v)| v.as_str())
+        .collect();
+    if ids.len() > 100 {
+        return error(400, "too many ids");
+    }
+    //unknown ids are left out rather than being an error
+    let data: Vec<Value> = ids
+        .iter()
+        .filter_map(|id| streams.iter().find(|s| s.user_id == *id))
+        .map(|s| {
+            json!({
+                "broadcaster_type": "",
+                "description": "",
+                "display_name": s.login,
+                "id": s.user_id,
+                "login": s.login,
+                "offline_image_url": "",
+                "profile_image_url": "",
+                "type": "",
+                "view_count": 0,
+            })
+        })
+        .collect();
+    (200, json!({ "data": data }))
+}
+
+fn error(status: u16, message: &str) -> (u16, Value) {
+    (
+        status,
+        json!({ "error": reason(status), "status": status, "message": message }),
+    )
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        _ => "Error",
+    }
+}
@@ -0,0 +1,252 @@
+//a stand in for twitch's chat server, for testing the client without going online. Plain TCP
+//only, so clients need `tls: false`.
+
+use crate::twitchclient::IrcServer;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::stream::StreamExt;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+pub(crate) type ClientId = usize;
+
+///How long to wait for clients to do something before failing the test
+const WAIT: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+struct State {
+    next_id: ClientId,
+    clients: HashMap<ClientId, Client>,
+    ///Every line sent by every client, in order
+    received: Vec<String>,
+    ///JOINs aren't answered while set, they're kept in `held` until released
+    hold_joins: bool,
+    held: Vec<(ClientId, String)>,
+}
+
+struct Client {
+    nick: Option<String>,
+    channels: HashSet<String>,
+    ///Dropping this closes the connection
+    lines: UnboundedSender<String>,
+}
+
+///Accepts anonymous logins, acknowledges CAP REQs, echoes JOINs and PARTs and answers PINGs.
+///Anything else a test wants sent, eg - PRIVMSGs or a RECONNECT, is sent with `send`.
+pub(crate) struct FakeTmi {
+    address: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeTmi {
+    pub(crate) async fn start() -> FakeTmi {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State::default()));
+        let accepting = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, accepting.clone()));
+            }
+        });
+        FakeTmi { address, state }
+    }
+
+    pub(crate) fn server(&self) -> IrcServer {
+        IrcServer {
+            address: self.address.to_string(),
+            tls: false,
+        }
+    }
+
+    ///Sends a line (with or without the \r\n) to a client
+    pub(crate) fn send(&self, id: ClientId, line: &str) {
+        let state = self.state.lock().unwrap();
+        let client = state.clients.get(&id).expect("client is connected");
+        let _ = client.lines.send(format!("{}\r\n", line.trim_end()));
+    }
+
+    ///Asks a client to reconnect, like twitch does before restarting a server
+    pub(crate) fn reconnect(&self, id: ClientId) {
+        self.send(id, ":tmi.twitch.tv RECONNECT");
+    }
+
+    ///Closes a client's connection without warning
+    pub(crate) fn disconnect(&self, id: ClientId) {
+        self.state.lock().unwrap().clients.remove(&id);
+    }
+
+    ///Stops answering JOINs, as if the server was slow to echo them
+    pub(crate) fn hold_joins(&self) {
+        self.state.lock().unwrap().hold_joins = true;
+    }
+
+    ///Answers the JOINs held since `hold_joins`, and any after
+    pub(crate) fn release_joins(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.hold_joins = false;
+        for (id, line) in std::mem::take(&mut state.held) {
+            if let Some(client) = state.clients.get_mut(&id) {
+                let _ = respond(client, &line);
+            }
+        }
+    }
+
+    ///Waits for `n` JOINs to be held
+    pub(crate) async fn held_joins(&self, n: usize) {
+        self.until(|state| Some(()).filter(|_| state.held.len() == n))
+            .await
+    }
+
+    pub(crate) fn received(&self) -> Vec<String> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    ///Waits for a client to be in `channel`, eg - "#ronni", and returns it
+    pub(crate) async fn joined(&self, channel: &str) -> ClientId {
+        self.until(|state| in_channel(state, channel, None)).await
+    }
+
+    ///Waits for a client other than `old` to be in `channel`
+    pub(crate) async fn rejoined(&self, channel: &str, old: ClientId) -> ClientId {
+        self.until(|state| in_channel(state, channel, Some(old)))
+            .await
+    }
+
+    ///Waits for the number of connections to be `n`
+    pub(crate) async fn connection_count(&self, n: usize) {
+        self.until(|state| Some(()).filter(|_| state.clients.len() == n))
+            .await
+    }
+
+    async fn until<T>(&self, f: impl Fn(&State) -> Option<T>) -> T {
+        let waiting = async {
+            loop {
+                if let Some(t) = f(&self.state.lock().unwrap()) {
+                    return t;
+                }
+                tokio::time::delay_for(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(WAIT, waiting)
+            .await
+            .expect("timed out waiting for the client")
+    }
+}
+
+fn in_channel(state: &State, channel: &str, not: Option<ClientId>) -> Option<ClientId> {
+    state
+        .clients
+        .iter()
+        .filter(|(id, _)| Some(**id) != not)
+        .find(|(_, c)| c.channels.contains(channel))
+        .map(|(id, _)| *id)
+}
+
+async fn serve(stream: TcpStream, state: Arc<Mutex<State>>) {
+    let (read, mut write) = stream.into_split();
+    let (lines, mut outgoing) = unbounded_channel::<String>();
+    let id = {
+        let mut state = state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        let client = Client {
+            nick: None,
+            channels: HashSet::new(),
+            lines,
+        };
+        state.clients.insert(id, client);
+        id
+    };
+    tokio::spawn(async move {
+        while let Some(line) = outgoing.recv().await {
+            if write.write_all(line.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+        //the client was disconnected
+        let _ = write.shutdown().await;
+    });
+
+    let mut incoming = BufReader::new(read).lines();
+    while let Some(Ok(line)) = incoming.next().await {
+        let mut state = state.lock().unwrap();
+        state.received.push(line.clone());
+        if state.hold_joins && line.starts_with("JOIN ") {
+            state.held.push((id, line));
+            continue;
+        }
+        let client = match state.clients.get_mut(&id) {
+            Some(c) => c,
+            None => return,
+        };
+        if respond(client, &line).is_err() {
+            state.clients.remove(&id);
+            return;
+        }
+    }
+    state.lock().unwrap().clients.remove(&id);
+}
+
+///Answers a line like twitch would. Errs if the client should be disconnected.
+fn respond(client: &mut Client, line: &str) -> Result<(), ()> {
+    let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+    let send = |client: &Client, line: String| {
+        let _ = client.lines.send(format!("{}\r\n", line));
+    };
+    match command {
+        "CAP" => {
+            let caps = args.strip_prefix("REQ :").unwrap_or(args);
+            send(client, format!(":tmi.twitch.tv CAP * ACK :{}", caps));
+        }
+        "NICK" if args.starts_with("justinfan") => {
+            let nick = args.to_string();
+            for (code, text) in &[
+                ("001", "Welcome, GLHF!"),
+                ("002", "Your host is tmi.twitch.tv"),
+                ("003", "This server is rather new"),
+                ("004", "-"),
+                ("375", "-"),
+                ("372", "You are in a maze of twisty passages, all alike."),
+                ("376", ">"),
+            ] {
+                send(
+                    client,
+                    format!(":tmi.twitch.tv {} {} :{}", code, nick, text),
+                );
+            }
+            client.nick = Some(nick);
+        }
+        "NICK" => {
+            send(
+                client,
+                ":tmi.twitch.tv NOTICE * :Login authentication failed".to_string(),
+            );
+            return Err(());
+        }
+        "PING" => send(
+            client,
+            format!(":tmi.twitch.tv PONG tmi.twitch.tv {}", args),
+        ),
+        "JOIN" | "PART" => {
+            let nick = client.nick.clone().ok_or(())?;
+            for channel in args.split(',') {
+                if command == "JOIN" {
+                    client.channels.insert(channel.to_string());
+                } else {
+                    client.channels.remove(channel);
+                }
+                send(client, format!("{} {} {}", prefix(&nick), command, channel));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn prefix(nick: &str) -> String {
+    format!(":{0}!{0}@{0}.tmi.twitch.tv", nick)
+}
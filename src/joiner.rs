@@ -0,0 +1,286 @@
+use crate::pool::{ConnectionEvent, ConnectionId};
+use chrono::Utc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::stream::StreamExt as _;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use twitchchat::{messages, EventStream, Writer};
+
+//https://dev.twitch.tv/docs/irc/guide#rate-limits
+//each channel in a JOIN counts as an attempt
+pub const JOINS_PER_PERIOD: usize = 20;
+pub const JOIN_PERIOD: Duration = Duration::from_secs(10);
+///How long to wait for the server to echo a JOIN before trying again
+pub const JOIN_TIMEOUT: Duration = Duration::from_secs(30);
+pub const MAX_JOIN_ATTEMPTS: u32 = 3;
+//irc lines are limited to 512 bytes including the trailing \r\n
+const MAX_LINE_LENGTH: usize = 510;
+const TICK: Duration = Duration::from_millis(500);
+
+///Sent to the joiner for a connection by the pool
+#[derive(Debug)]
+pub enum JoinCommand {
+    Join(String),
+    Part(String),
+    ///Stop trying to join a channel without sending a PART
+    Forget(String),
+}
+
+///Sliding window limiter on the number of channels joined
+struct JoinLimiter {
+    sent: VecDeque<Instant>,
+    limit: usize,
+    period: Duration,
+}
+
+impl JoinLimiter {
+    fn new(limit: usize, period: Duration) -> JoinLimiter {
+        JoinLimiter {
+            sent: VecDeque::with_capacity(limit),
+            limit,
+            period,
+        }
+    }
+
+    fn available(&mut self, now: Instant) -> usize {
+        while let Some(t) = self.sent.front() {
+            if now.duration_since(*t) >= self.period {
+                self.sent.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.limit - self.sent.len()
+    }
+
+    fn record(&mut self, now: Instant, n: usize) {
+        self.sent.extend(std::iter::repeat_n(now, n));
+    }
+}
+
+///Decides when to send JOINs for a single connection. Channels are batched into as few JOIN
+///lines as the rate limit allows and retried if the server doesn't echo the JOIN back.
+pub(crate) struct JoinScheduler {
+    queue: VecDeque<(String, u32)>,
+    awaiting: HashMap<String, (Instant, u32)>,
+    limiter: JoinLimiter,
+}
+
+impl JoinScheduler {
+    pub(crate) fn new() -> JoinScheduler {
+        JoinScheduler {
+            queue: VecDeque::new(),
+            awaiting: HashMap::new(),
+            limiter: JoinLimiter::new(JOINS_PER_PERIOD, JOIN_PERIOD),
+        }
+    }
+
+    pub(crate) fn push(&mut self, channel: String) {
+        self.queue.push_back((channel, 0));
+    }
+
+    ///Returns true if we were waiting for this channel
+    pub(crate) fn confirm(&mut self, channel: &str) -> bool {
+        self.awaiting.remove(channel).is_some()
+    }
+
+    pub(crate) fn cancel(&mut self, channel: &str) {
+        self.awaiting.remove(channel);
+        self.queue.retain(|(c, _)| c != channel);
+    }
+
+    pub(crate) fn is_idle(&self) -> bool {
+        self.queue.is_empty() && self.awaiting.is_empty()
+    }
+
+    ///Channels to put in the next JOIN line, if the rate limit allows any
+    pub(crate) fn next_batch(&mut self, now: Instant) -> Vec<String> {
+        let available = self.limiter.available(now);
+        let mut batch = Vec::new();
+        let mut line_length = "JOIN ".len();
+        while batch.len() < available {
+            let len = match self.queue.front() {
+                Some((c, _)) => c.len() + 2, //# and ,
+                None => break,
+            };
+            if !batch.is_empty() && line_length + len > MAX_LINE_LENGTH {
+                break;
+            }
+            let (channel, attempts) = self.queue.pop_front().unwrap();
+            line_length += len;
+            self.awaiting.insert(channel.clone(), (now, attempts + 1));
+            batch.push(channel);
+        }
+        self.limiter.record(now, batch.len());
+        batch
+    }
+
+    ///Requeues channels that haven't been confirmed in time. Returns the ones that have run out
+    ///of attempts.
+    pub(crate) fn expire(&mut self, now: Instant) -> Vec<String> {
+        let expired: Vec<(String, u32)> = self
+            .awaiting
+            .iter()
+            .filter(|(_, (sent, _))| now.duration_since(*sent) >= JOIN_TIMEOUT)
+            .map(|(c, (_, attempts))| (c.to_string(), *attempts))
+            .collect();
+
+        let mut failed = Vec::new();
+        for (channel, attempts) in expired {
+            self.awaiting.remove(&channel);
+            if attempts >= MAX_JOIN_ATTEMPTS {
+                failed.push(channel);
+            } else {
+                self.queue.push_front((channel, attempts));
+            }
+        }
+        failed
+    }
+}
+
+pub(crate) fn join_line(channels: &[String]) -> String {
+    let channels: Vec<String> = channels.iter().map(|c| format!("#{}", c)).collect();
+    format!("JOIN {}", channels.join(","))
+}
+
+///Drives the scheduler for a connection until the connection or the pool goes away. JOIN and
+///PART echoes for our own nick are passed on to the pool.
+pub(crate) async fn run(
+    id: ConnectionId,
+    nick: String,
+    mut commands: UnboundedReceiver<JoinCommand>,
+    mut writer: Writer,
+    mut joins: EventStream<Arc<messages::Join<'static>>>,
+    mut parts: EventStream<Arc<messages::Part<'static>>>,
+    events: UnboundedSender<ConnectionEvent>,
+) {
+    let mut scheduler = JoinScheduler::new();
+    let mut ticker = tokio::time::interval(TICK);
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(JoinCommand::Join(c)) => scheduler.push(c),
+                Some(JoinCommand::Part(c)) => {
+                    scheduler.cancel(&c);
+                    if writer.part(&c).await.is_err() {
+                        break;
+                    }
+                }
+                Some(JoinCommand::Forget(c)) => scheduler.cancel(&c),
+                None => break,
+            },
+            Some(join) = joins.next() => {
+                if join.name.eq_ignore_ascii_case(&nick) {
+                    let channel = join.channel.trim_start_matches('#').to_string();
+                    scheduler.confirm(&channel);
+                    let _ = events.send(ConnectionEvent::Joined { id, channel });
+                }
+            }
+            Some(part) = parts.next() => {
+                if part.name.eq_ignore_ascii_case(&nick) {
+                    let channel = part.channel.trim_start_matches('#').to_string();
+                    let _ = events.send(ConnectionEvent::Parted { id, channel });
+                }
+            }
+            _ = ticker.tick() => {}
+            else => break,
+        }
+
+        if scheduler.is_idle() {
+            continue;
+        }
+        let now = Instant::now();
+        for channel in scheduler.expire(now) {
+            eprintln!(
+                "[{}] gave up joining {} on connection {} after {} attempts",
+                Utc::now(),
+                channel,
+                id,
+                MAX_JOIN_ATTEMPTS
+            );
+            let _ = events.send(ConnectionEvent::JoinFailed { id, channel });
+        }
+        let batch = scheduler.next_batch(now);
+        if !batch.is_empty() && writer.raw(join_line(&batch)).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(range: std::ops::Range<u32>) -> Vec<String> {
+        range.map(|i| format!("channel{}", i)).collect()
+    }
+
+    fn scheduler_with(channels: Vec<String>) -> JoinScheduler {
+        let mut s = JoinScheduler::new();
+        for c in channels {
+            s.push(c);
+        }
+        s
+    }
+
+    #[test]
+    fn test_join_line() {
+        assert_eq!(join_line(&["a".to_string(), "b".to_string()]), "JOIN #a,#b");
+    }
+
+    #[test]
+    fn test_batches_respect_rate_limit() {
+        let mut s = scheduler_with(names(0..50));
+        let start = Instant::now();
+        let first = s.next_batch(start);
+        assert_eq!(first, names(0..JOINS_PER_PERIOD as u32));
+        assert!(s.next_batch(start + Duration::from_secs(1)).is_empty());
+
+        let second = s.next_batch(start + JOIN_PERIOD);
+        assert_eq!(second.len(), JOINS_PER_PERIOD);
+        assert!(second.iter().all(|c| !first.contains(c)));
+    }
+
+    #[test]
+    fn test_batches_fit_in_a_line() {
+        let long: Vec<String> = (0..JOINS_PER_PERIOD)
+            .map(|i| format!("{:0>25}", i))
+            .collect();
+        let mut s = scheduler_with(long);
+        let batch = s.next_batch(Instant::now());
+        assert!(!batch.is_empty());
+        assert!(batch.len() < JOINS_PER_PERIOD);
+        assert!(join_line(&batch).len() <= MAX_LINE_LENGTH);
+    }
+
+    #[test]
+    fn test_unconfirmed_joins_are_retried_then_fail() {
+        let mut s = scheduler_with(names(0..2));
+        let mut now = Instant::now();
+        assert_eq!(s.next_batch(now).len(), 2);
+        assert!(s.confirm("channel0"));
+        assert!(!s.confirm("channel0"));
+
+        for _ in 1..MAX_JOIN_ATTEMPTS {
+            now += JOIN_TIMEOUT;
+            assert!(s.expire(now).is_empty());
+            assert_eq!(s.next_batch(now), names(1..2));
+        }
+        now += JOIN_TIMEOUT;
+        assert_eq!(s.expire(now), names(1..2));
+        assert!(s.is_idle());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut s = scheduler_with(names(0..2));
+        s.next_batch(Instant::now());
+        s.push("channel2".to_string());
+        s.cancel("channel0");
+        s.cancel("channel2");
+        assert!(!s.confirm("channel0"));
+        assert!(s.confirm("channel1"));
+        assert!(s.is_idle());
+    }
+}
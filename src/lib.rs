@@ -0,0 +1,40 @@
+#[macro_use]
+extern crate diesel;
+
+pub mod archive;
+pub mod channels;
+pub mod collector;
+pub mod config;
+pub mod db;
+pub mod error;
+pub mod export;
+#[cfg(test)]
+mod fake_helix;
+#[cfg(test)]
+mod fake_tmi;
+mod joiner;
+pub mod pool;
+#[allow(non_local_definitions)]
+pub mod postgres;
+pub mod refresh;
+pub mod reparse;
+pub mod replay;
+pub mod sender;
+pub mod state;
+pub mod twitchclient;
+pub mod types;
+
+//diesel 1.x macros generate impls inside anonymous consts
+#[allow(non_local_definitions)]
+mod models;
+#[allow(non_local_definitions)]
+mod pg_schema;
+#[allow(non_local_definitions)]
+mod schema;
+
+pub use collector::{Channels, Collector, CollectorBuilder, Sink, Transport};
+pub use config::Config;
+pub use db::MessageSink;
+pub use sender::messages;
+pub use state::{ChannelState, ChannelTracker};
+pub use types::{Record, TwitchMessage};
@@ -1,212 +1,191 @@
-#[macro_use]
-extern crate diesel;
-
-mod db;
-mod models;
-mod schema;
-
-mod types;
-use types::TwitchMessage;
-mod channels;
-mod error;
-use std::collections::HashSet;
-use std::iter::FromIterator;
-use std::sync::mpsc::Sender;
-mod twitchclient;
-
-#[tokio::main]
-async fn main() {
-    //dotenv().ok();
-    let max_channels = 1000u64; //env::var("MAX_CHANNELS").unwrap().parse::<u64>().unwrap();
-                                //let channels_per_controller = env::var("CHANNELS_PER_CONTROLLER")
-                                //    .unwrap()
-                                //    .parse::<u64>()
-                                //    .unwrap();
-                                //let refresh_interval = env::var("REFRESH_INTERVAL")
-                                //   .unwrap()
-                                //  .parse::<u64>()
-                                // .unwrap();
-
-    let db_conn: Sender<TwitchMessage> = db::DB::connection().unwrap();
-
-    let raw_channels = channels::top_connections(max_channels).await;
-    let chans = cleanup_channels(raw_channels, max_channels);
-
-    //TODO refreshing/joining new channels
-    twitchclient::get_messages(chans, db_conn).await;
-    //let controllers = ControllerGroup::init_simple(chans, channels_per_controller, db_conn.clone());
-    //loop {
-    //    thread::sleep(Duration::from_secs(refresh_interval));
-    //    refresh_channels(&controllers);
-    //}
+use std::path::PathBuf;
+use structopt::StructOpt;
+use twitch_chat_parser::config::split_channels;
+use twitch_chat_parser::error::MyError;
+use twitch_chat_parser::export::export_messages;
+use twitch_chat_parser::reparse::reparse_messages;
+use twitch_chat_parser::replay::{ReplayConfig, ReplaySpeed};
+use twitch_chat_parser::{CollectorBuilder, Config, Transport};
+
+///Collects chat from twitch channels. Options given here override the config file and
+///environment.
+#[derive(StructOpt, Debug)]
+struct Opt {
+    ///Config file to read [default: config.toml if it exists]
+    #[structopt(short, long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    ///Number of top channels to join
+    #[structopt(long)]
+    max_channels: Option<u64>,
+    ///Comma separated list of channels to join instead of the top channels
+    #[structopt(long)]
+    channels: Option<String>,
+    #[structopt(long)]
+    database_url: Option<String>,
+    ///Write newline delimited json files to this directory instead of the database
+    #[structopt(long, parse(from_os_str))]
+    archive_directory: Option<PathBuf>,
+    ///Number of messages inserted per transaction
+    #[structopt(long)]
+    batch_size: Option<usize>,
+    ///Seconds between channel refreshes
+    #[structopt(long)]
+    refresh_interval: Option<u64>,
+    ///Channels joined on each IRC connection
+    #[structopt(long)]
+    channels_per_connection: Option<usize>,
+    ///Record other users joining and leaving channels
+    #[structopt(long)]
+    membership: bool,
+    ///Don't request USERNOTICE, CLEARCHAT, CLEARMSG and ROOMSTATE
+    #[structopt(long)]
+    no_commands: bool,
+    #[structopt(long)]
+    client_id: Option<String>,
+    ///Helix app access token
+    #[structopt(long)]
+    token: Option<String>,
+    ///IRC server to connect to instead of twitch's, as host:port
+    #[structopt(long)]
+    irc_address: Option<String>,
+    ///Connect to the IRC server without TLS
+    #[structopt(long)]
+    no_tls: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
-fn cleanup_channels(mut chans: Vec<String>, expected: u64) -> Vec<String> {
-    let mut seen_set = HashSet::<String>::with_capacity(chans.len());
+#[derive(StructOpt, Debug)]
+enum Command {
+    ///Write the messages table to parquet files, one per channel per day, instead of collecting
+    ExportParquet {
+        ///Directory to write the files to
+        #[structopt(long, parse(from_os_str), default_value = "parquet")]
+        output: PathBuf,
+    },
+    ///Parse the raw IRC line of every stored message again and rewrite what's derived from it,
+    ///batch_size messages at a time. An interrupted reparse carries on where it stopped.
+    Reparse {
+        ///Start from the beginning instead of carrying on from a previous reparse
+        #[structopt(long)]
+        restart: bool,
+    },
+    ///Collect from a file of raw IRC lines, eg - exported raw_message values, instead of twitch
+    Replay {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        ///How many times faster than the original timing to replay, or max for as fast as
+        ///possible
+        #[structopt(long, default_value = "max")]
+        speed: ReplaySpeed,
+    },
+}
 
-    chans.retain(|c| {
-        let seen = !seen_set.insert(c.to_string());
-        if seen {
-            eprintln!(
-                "channel {} was found twice in channels returned by API, removing duplicate",
-                c
-            );
+impl Opt {
+    fn apply(self, config: &mut Config) {
+        if let Some(v) = self.max_channels {
+            config.max_channels = v;
+        }
+        if let Some(v) = self.channels {
+            config.channels = Some(split_channels(&v));
+        }
+        if let Some(v) = self.database_url {
+            config.database_url = Some(v);
+        }
+        if let Some(v) = self.archive_directory {
+            config.archive_directory(v);
+        }
+        if let Some(v) = self.batch_size {
+            config.batch_size = v;
+        }
+        if let Some(v) = self.refresh_interval {
+            config.refresh_interval = v;
+        }
+        if let Some(v) = self.channels_per_connection {
+            config.channels_per_connection = v;
+        }
+        if self.membership {
+            config.capabilities.membership = true;
+        }
+        if self.no_commands {
+            config.capabilities.commands = false;
+        }
+        if let Some(v) = self.client_id {
+            config.helix.client_id = v;
+        }
+        if let Some(v) = self.token {
+            config.helix.token = Some(v);
+        }
+        if let Some(v) = self.irc_address {
+            config.irc.address = v;
+        }
+        if self.no_tls {
+            config.irc.tls = false;
         }
-        !seen
-    });
-    if chans.len() < expected as usize {
-        eprintln!(
-            "API returned fewer channels than expected. Expected {}, got {}",
-            expected,
-            chans.len()
-        );
     }
-    assert_eq!(
-        chans.len(),
-        HashSet::<&String>::from_iter(chans.iter()).len()
-    ); //check there are no duplicates
-
-    chans
 }
 
-//fn refresh_channels(controllers: &ControllerGroup) {
-//    refresh_channels_inner(
-//        controllers,
-//        channels::top_connections(controllers.max_channels),
-//    )
-//}
-//
-/////split out for testing purposes
-/////There are 3 loops over all joined channels:
-/////1. Mark channels not returned in API to be left
-/////2. for the channels returned by the API, swap out a channel to be left for a fresh one
-/////3. If the API happens to return a higher (closer to expected) number of channels  than it did
-/////   last time then join these
-/////   too
-//fn refresh_channels_inner(controllerGroup: &ControllerGroup, channels: Vec<String>) {
-//    let mut top_channels: HashSet<String> = HashSet::from_iter(channels.into_iter());
-//    let controllers = &controllerGroup.controllers;
-//    let mut to_leave: Vec<Vec<_>> = (0..controllers.len()).map(|_| Vec::new()).collect();
-//    for (i, c) in controllers.iter().enumerate() {
-//        for l in c.list().unwrap() {
-//            if !top_channels.remove(&l) {
-//                to_leave[i].push(l);
-//            }
-//        }
-//    }
-//
-//    let mut it = top_channels.drain(); //To join
-//    for (i, v) in to_leave.iter().enumerate() {
-//        for leaving in v {
-//            //because of the API issues the length of the iterator can be shorter than the length
-//            //of the channels to leave
-//            match it.next() {
-//                Some(c) => {
-//                    controllers[i].join(c);
-//                    controllers[i].part(leaving.to_string());
-//                }
-//                None => break,
-//            }
-//        }
-//    }
-//
-//    for (i, c) in controllers.iter().enumerate() {
-//        while c.list().unwrap().len() < controllerGroup.channels_per_controller as usize {
-//            match it.next() {
-//                Some(ch) => {
-//                    controllers[i].join(ch);
-//                }
-//                None => break,
-//            }
-//        }
-//    }
-//}
-
-#[cfg(test)]
-mod test {
-    use super::*;
-    use controller::test::*;
+fn load_config() -> Result<(Config, Option<Command>), MyError> {
+    let mut opt = Opt::from_args();
+    let command = opt.command.take();
+    let mut config = Config::from_file(opt.config.as_deref())?;
+    config.apply_env()?;
+    opt.apply(&mut config);
+    config.validate()?;
+    Ok((config, command))
+}
 
-    //convenience
-    impl ControllerGroup {
-        fn init_test(channels: Vec<String>, channels_per_controller: u64) -> ControllerGroup {
-            let chans_length = channels.len() as u64;
-            Self::init_inner(
-                channels,
-                chans_length,
-                channels_per_controller,
-                channel().0,
-                |c, _| Box::new(TestController::init(c)),
-            )
+async fn run_command(config: &Config, command: Command) -> Result<(), MyError> {
+    let database_url = || {
+        config
+            .database_url
+            .as_deref()
+            .ok_or_else(|| MyError::Config("database_url must be set to run a command".to_string()))
+    };
+    match command {
+        Command::ExportParquet { output } => {
+            let exported = export_messages(database_url()?, &output)?;
+            println!("exported {} messages to {}", exported, output.display());
+        }
+        Command::Reparse { restart } => {
+            let stats = reparse_messages(database_url()?, config.batch_size, restart)?;
+            println!(
+                "reparsed {} messages, skipped {} that couldn't be parsed",
+                stats.reparsed, stats.skipped
+            );
+        }
+        Command::Replay { file, speed } => {
+            let transport = Transport::Replay(ReplayConfig { path: file, speed });
+            CollectorBuilder::from(config)
+                .transport(transport)
+                .build()
+                .run()
+                .await?;
         }
     }
+    Ok(())
+}
 
-    #[test]
-    fn basic_test() {
-        let (_, controller) = Controller::init(Vec::new()).unwrap();
-        let top_channel = channels::top_connections(1).get(0).unwrap().to_string();
-        controller.join(top_channel.clone()).unwrap();
-        std::thread::sleep(std::time::Duration::from_secs(1)); //TODO only added to joined list when message received from server
-        assert_eq!(controller.list(), Some(vec![top_channel.clone()]));
-
-        controller.part(top_channel.clone()).unwrap();
-        std::thread::sleep(std::time::Duration::from_secs(1)); //TODO only added to joined list when message received from server
-        assert_eq!(controller.list().unwrap(), Vec::<String>::new());
-    }
-
-    #[test]
-    fn test_join_through_reference() {
-        let controller = TestController::new();
-        (&controller).join("a_channel".to_string());
-        assert_eq!(controller.list(), Some(vec!["a_channel".to_string()]));
-    }
-
-    //TODO might be nice to have a property test here
-    fn assert_refresh_works(initial: ControllerGroup, final_channels: Vec<String>) {
-        refresh_channels_inner(&initial, final_channels.clone());
-        let refresh_channels: Vec<String> = initial.list_channels().into_iter().flatten().collect();
-
-        let refresh_channels_set =
-            HashSet::<String>::from_iter(refresh_channels.clone().into_iter());
-        assert_eq!(refresh_channels.len(), refresh_channels_set.len());
-        assert_eq!(
-            HashSet::from_iter(final_channels.clone().into_iter()),
-            refresh_channels_set
-        );
-    }
-
-    #[test]
-    fn test_refresh_channels_no_op() {
-        let new_channels: Vec<_> = (0..10).map(|i| i.to_string()).collect();
-        assert_refresh_works(
-            ControllerGroup::init_test(new_channels.clone(), 2),
-            new_channels,
-        );
-    }
-
-    #[test]
-    fn test_refresh_channels_single_replacement() {
-        let channels: Vec<_> = (0..100).map(|i| i.to_string()).collect();
-        let mut new_channels = channels.clone();
-        new_channels.pop();
-        new_channels.push("101".to_string());
-        assert_eq!(channels.len(), new_channels.len());
-        assert_refresh_works(ControllerGroup::init_test(channels, 10), new_channels);
-    }
+#[tokio::main]
+async fn main() {
+    let (config, command) = match load_config() {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    #[test]
-    fn test_channels_replace_all() {
-        let channels: Vec<_> = (0..100).map(|i| i.to_string()).collect();
-        let new_channels: Vec<_> = (300..400).map(|i| i.to_string()).collect();
-        assert_eq!(channels.len(), new_channels.len());
-        assert_refresh_works(ControllerGroup::init_test(channels, 10), new_channels);
+    if let Some(command) = command {
+        if let Err(e) = run_command(&config, command).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
     }
 
-    #[test]
-    fn simulate_api_issues_refresh() {
-        let channels: Vec<_> = (0..100).map(|i| i.to_string()).collect();
-        let group = ControllerGroup::init_test(channels, 10);
-        let new_channels: Vec<_> = (10..101).map(|i| i.to_string()).collect();
-        refresh_channels_inner(&group, new_channels);
+    if let Err(e) = CollectorBuilder::from(&config).build().run().await {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
 }
@@ -1,46 +1,395 @@
-use super::schema::messages;
-use crate::types::TwitchMessage;
+use super::schema::{
+    clear_chats, clear_msgs, message_badges, message_emotes, message_replies, messages, outages,
+    presence, room_states, user_notices,
+};
+use crate::error::MyError;
+use crate::types::{
+    Badge, EmoteUsage, Outage, Presence, TwitchClearChat, TwitchClearMsg, TwitchMessage,
+    TwitchRoomState, TwitchUserNotice,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use diesel::sql_types::{BigInt, Binary, Bool, Integer, Nullable, Text};
+use uuid::Uuid;
 
-//TODO edit migrations
-#[derive(Insertable)]
+#[derive(Insertable, QueryableByName, AsChangeset)]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct Message {
-    pub id: String,
+    #[sql_type = "Binary"]
+    pub id: Vec<u8>,
+    #[sql_type = "Nullable<Text>"]
     pub badge_info: Option<String>,
+    #[sql_type = "Nullable<Text>"]
     pub badges: Option<String>,
+    #[sql_type = "Nullable<Integer>"]
     pub bits: Option<i32>,
+    #[sql_type = "Nullable<Text>"]
     pub color: Option<String>, //TODO hex rgb
+    #[sql_type = "Text"]
     pub display_name: String,
+    #[sql_type = "Nullable<Text>"]
     pub emotes: Option<String>,
+    #[sql_type = "Nullable<Bool>"]
     pub mod_: Option<bool>,
+    #[sql_type = "Integer"]
     pub room_id: i32,
-    pub tmi_sent_ts: String, //TODO timestamp
+    #[sql_type = "BigInt"]
+    pub tmi_sent_ts: i64,
+    #[sql_type = "Text"]
     pub user_id: String,
+    #[sql_type = "Text"]
     pub channel: String,
+    #[sql_type = "Text"]
     pub message: String,
+    #[sql_type = "Text"]
     pub raw_message: String,
+    #[sql_type = "Nullable<Integer>"]
+    pub subscription_months: Option<i32>,
+    #[sql_type = "Nullable<Text>"]
+    pub client_nonce: Option<String>,
+    #[sql_type = "Nullable<Bool>"]
+    pub emote_only: Option<bool>,
+    #[sql_type = "Nullable<Bool>"]
+    pub first_msg: Option<bool>,
+    #[sql_type = "Nullable<Text>"]
+    pub flags: Option<String>,
+    #[sql_type = "Nullable<Bool>"]
+    pub returning_chatter: Option<bool>,
+    #[sql_type = "Nullable<Bool>"]
+    pub subscriber: Option<bool>,
+    #[sql_type = "Nullable<Bool>"]
+    pub turbo: Option<bool>,
+    #[sql_type = "Nullable<Text>"]
+    pub user_type: Option<String>,
+    #[sql_type = "Nullable<Bool>"]
+    pub vip: Option<bool>,
+    ///json object of tags there isn't a column for
+    #[sql_type = "Nullable<Text>"]
+    pub other_tags: Option<String>,
+    #[sql_type = "Bool"]
+    pub action: bool,
 }
 
 impl From<TwitchMessage> for Message {
     fn from(message: TwitchMessage) -> Self {
         Message {
-            id: message.tags.id.to_string(),
-            badge_info: message.tags.badge_info,
-            badges: message.tags.badges.map(vec_to_json),
+            id: uuid_to_bytes(message.tags.id),
+            subscription_months: message.tags.subscription_months(),
+            badge_info: message.tags.badge_info.map(|b| tag_text(&b)),
+            badges: message.tags.badges.map(tag_json),
             bits: message.tags.bits,
             color: message.tags.color,
             display_name: message.tags.display_name,
-            emotes: message.tags.emotes.map(vec_to_json),
+            emotes: message.tags.emotes.map(tag_json),
             mod_: message.tags.moderator,
             room_id: message.tags.room_id,
-            tmi_sent_ts: message.tags.tmi_sent_ts.to_rfc3339(),
+            tmi_sent_ts: timestamp_to_millis(message.tags.tmi_sent_ts),
             user_id: message.tags.user_id,
             channel: message.channel,
             message: message.message,
-            raw_message: message.raw.trim().to_string(),
+            raw_message: raw_line(&message.raw),
+            client_nonce: message.tags.client_nonce,
+            emote_only: message.tags.emote_only,
+            first_msg: message.tags.first_msg,
+            flags: message.tags.flags.map(|f| tag_text(&f)),
+            returning_chatter: message.tags.returning_chatter,
+            subscriber: message.tags.subscriber,
+            turbo: message.tags.turbo,
+            user_type: message.tags.user_type,
+            vip: message.tags.vip,
+            other_tags: Some(message.tags.other)
+                .filter(|o| !o.is_empty())
+                .map(|o| serde_json::to_string(&o).unwrap_or_default()),
+            action: message.action,
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "message_replies"]
+pub struct NewMessageReply {
+    pub message_id: Vec<u8>,
+    pub parent_msg_id: Vec<u8>,
+    pub parent_user_id: Option<String>,
+    pub parent_user_login: Option<String>,
+    pub parent_display_name: Option<String>,
+    pub parent_msg_body: Option<String>,
+    pub thread_parent_msg_id: Option<Vec<u8>>,
+    pub thread_parent_user_login: Option<String>,
+}
+
+impl NewMessageReply {
+    pub fn from_message(message: &TwitchMessage) -> Option<NewMessageReply> {
+        let reply = message.tags.reply.as_deref()?.clone();
+        Some(NewMessageReply {
+            message_id: uuid_to_bytes(message.tags.id),
+            parent_msg_id: uuid_to_bytes(reply.parent_msg_id),
+            parent_user_id: reply.parent_user_id,
+            parent_user_login: reply.parent_user_login,
+            parent_display_name: reply.parent_display_name,
+            parent_msg_body: reply.parent_msg_body,
+            thread_parent_msg_id: reply.thread_parent_msg_id.map(uuid_to_bytes),
+            thread_parent_user_login: reply.thread_parent_user_login,
+        })
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "message_badges"]
+pub struct NewMessageBadge {
+    pub message_id: Vec<u8>,
+    pub name: String,
+    pub version: String,
+}
+
+impl NewMessageBadge {
+    pub fn from_message(message: &TwitchMessage) -> Vec<NewMessageBadge> {
+        let badges = message.tags.badges.as_deref().unwrap_or_default();
+        NewMessageBadge::from_badges(&uuid_to_bytes(message.tags.id), badges)
+    }
+
+    pub fn from_badges(message_id: &[u8], badges: &[Badge]) -> Vec<NewMessageBadge> {
+        badges
+            .iter()
+            .map(|b| NewMessageBadge {
+                message_id: message_id.to_vec(),
+                name: b.name.clone(),
+                version: b.version.clone(),
+            })
+            .collect()
+    }
+}
+
+///One row per place an emote is used
+#[derive(Insertable)]
+#[table_name = "message_emotes"]
+pub struct NewMessageEmote {
+    pub message_id: Vec<u8>,
+    pub emote_id: String,
+    pub start_index: i32,
+    pub end_index: i32,
+    pub name: String,
+}
+
+impl NewMessageEmote {
+    pub fn from_message(message: &TwitchMessage) -> Vec<NewMessageEmote> {
+        let emotes = message.tags.emotes.as_deref().unwrap_or_default();
+        NewMessageEmote::from_emotes(&uuid_to_bytes(message.tags.id), emotes, &message.message)
+    }
+
+    pub fn from_emotes(
+        message_id: &[u8],
+        emotes: &[EmoteUsage],
+        message: &str,
+    ) -> Vec<NewMessageEmote> {
+        emotes
+            .iter()
+            .flat_map(|e| {
+                e.ranges.iter().map(move |r| NewMessageEmote {
+                    message_id: message_id.to_vec(),
+                    emote_id: e.emote_id.clone(),
+                    start_index: r.start as i32,
+                    end_index: r.end as i32,
+                    name: r.text(message).unwrap_or_default().to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "outages"]
+pub struct NewOutage {
+    pub channel: String,
+    pub started_at: i64,
+    pub ended_at: i64,
+    pub reason: String,
+}
+
+impl From<Outage> for NewOutage {
+    fn from(outage: Outage) -> Self {
+        NewOutage {
+            channel: outage.channel,
+            started_at: timestamp_to_millis(outage.start),
+            ended_at: timestamp_to_millis(outage.end),
+            reason: outage.reason,
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "user_notices"]
+pub struct NewUserNotice {
+    pub id: Vec<u8>,
+    pub channel: String,
+    pub room_id: i32,
+    pub msg_id: String,
+    pub user_id: Option<String>,
+    pub login: Option<String>,
+    pub display_name: Option<String>,
+    pub system_msg: Option<String>,
+    pub message: Option<String>,
+    pub params: String,
+    pub tmi_sent_ts: i64,
+    pub raw_message: String,
+}
+
+impl From<TwitchUserNotice> for NewUserNotice {
+    fn from(notice: TwitchUserNotice) -> Self {
+        NewUserNotice {
+            id: uuid_to_bytes(notice.id),
+            channel: notice.channel,
+            room_id: notice.room_id,
+            msg_id: notice.msg_id,
+            user_id: notice.user_id,
+            login: notice.login,
+            display_name: notice.display_name,
+            system_msg: notice.system_msg,
+            message: notice.message,
+            params: serde_json::to_string(&notice.params).unwrap_or_default(),
+            tmi_sent_ts: timestamp_to_millis(notice.tmi_sent_ts),
+            raw_message: raw_line(&notice.raw),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "clear_chats"]
+pub struct NewClearChat {
+    pub channel: String,
+    pub room_id: i32,
+    pub target_login: Option<String>,
+    pub target_user_id: Option<String>,
+    pub ban_duration: Option<i32>,
+    pub tmi_sent_ts: i64,
+    pub raw_message: String,
+}
+
+impl From<TwitchClearChat> for NewClearChat {
+    fn from(clear: TwitchClearChat) -> Self {
+        NewClearChat {
+            channel: clear.channel,
+            room_id: clear.room_id,
+            target_login: clear.target_login,
+            target_user_id: clear.target_user_id,
+            ban_duration: clear.ban_duration,
+            tmi_sent_ts: timestamp_to_millis(clear.tmi_sent_ts),
+            raw_message: raw_line(&clear.raw),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "clear_msgs"]
+pub struct NewClearMsg {
+    pub channel: String,
+    pub login: Option<String>,
+    pub target_msg_id: Vec<u8>,
+    pub message: Option<String>,
+    pub tmi_sent_ts: i64,
+    pub raw_message: String,
+}
+
+impl From<TwitchClearMsg> for NewClearMsg {
+    fn from(clear: TwitchClearMsg) -> Self {
+        NewClearMsg {
+            channel: clear.channel,
+            login: clear.login,
+            target_msg_id: uuid_to_bytes(clear.target_msg_id),
+            message: clear.message,
+            tmi_sent_ts: timestamp_to_millis(clear.tmi_sent_ts),
+            raw_message: raw_line(&clear.raw),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "room_states"]
+pub struct NewRoomState {
+    pub channel: String,
+    pub room_id: i32,
+    pub emote_only: Option<bool>,
+    pub followers_only: Option<i32>,
+    pub r9k: Option<bool>,
+    pub slow: Option<i32>,
+    pub subs_only: Option<bool>,
+    pub received_at: i64,
+    pub raw_message: String,
+}
+
+impl From<TwitchRoomState> for NewRoomState {
+    fn from(state: TwitchRoomState) -> Self {
+        NewRoomState {
+            channel: state.channel,
+            room_id: state.room_id,
+            emote_only: state.emote_only,
+            followers_only: state.followers_only,
+            r9k: state.r9k,
+            slow: state.slow,
+            subs_only: state.subs_only,
+            received_at: timestamp_to_millis(state.received_at),
+            raw_message: raw_line(&state.raw),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "presence"]
+pub struct NewPresence {
+    pub channel: String,
+    pub login: String,
+    pub event: String,
+    pub received_at: i64,
+}
+
+impl From<Presence> for NewPresence {
+    fn from(presence: Presence) -> Self {
+        NewPresence {
+            channel: presence.channel,
+            login: presence.login,
+            event: presence.kind.as_str().to_string(),
+            received_at: timestamp_to_millis(presence.received_at),
         }
     }
 }
 
+///Message and notice ids are stored as their 16 bytes
+pub fn uuid_to_bytes(id: Uuid) -> Vec<u8> {
+    id.as_bytes().to_vec()
+}
+
+pub fn uuid_from_bytes(bytes: &[u8]) -> Result<Uuid, MyError> {
+    Uuid::from_slice(bytes).map_err(|_| MyError::Parse("id is not 16 bytes"))
+}
+
+///Timestamps are stored as milliseconds since the epoch, which is all twitch sends for tmi_sent_ts
+pub fn timestamp_to_millis(ts: DateTime<Utc>) -> i64 {
+    ts.timestamp_millis()
+}
+
+pub fn timestamp_from_millis(ms: i64) -> Result<DateTime<Utc>, MyError> {
+    Utc.timestamp_millis_opt(ms)
+        .single()
+        .ok_or(MyError::Parse("tmi_sent_ts out of range"))
+}
+
+///The IRC line as it was received, without the \r\n
+pub(crate) fn raw_line(raw: &str) -> String {
+    raw.strip_suffix("\r\n").unwrap_or(raw).to_string()
+}
+
+///badge_info is kept as it was in the tag
+pub(crate) fn tag_text<T: ToString>(v: &[T]) -> String {
+    v.iter()
+        .map(T::to_string)
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+///Badges and emotes columns hold the tag text of each one
+fn tag_json<T: ToString>(v: Vec<T>) -> String {
+    vec_to_json(v.iter().map(T::to_string).collect())
+}
+
 fn vec_to_json<T: serde::Serialize>(v: Vec<T>) -> String {
     serde_json::to_string(&v).unwrap_or_default()
 }
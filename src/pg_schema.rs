@@ -0,0 +1,152 @@
+table! {
+    clear_chats (id) {
+        id -> Int8,
+        channel -> Text,
+        room_id -> Int4,
+        target_login -> Nullable<Text>,
+        target_user_id -> Nullable<Text>,
+        ban_duration -> Nullable<Int4>,
+        tmi_sent_ts -> Timestamptz,
+        raw_message -> Text,
+    }
+}
+
+table! {
+    clear_msgs (id) {
+        id -> Int8,
+        channel -> Text,
+        login -> Nullable<Text>,
+        target_msg_id -> Uuid,
+        message -> Nullable<Text>,
+        tmi_sent_ts -> Timestamptz,
+        raw_message -> Text,
+    }
+}
+
+table! {
+    message_badges (message_id, name) {
+        message_id -> Uuid,
+        name -> Text,
+        version -> Text,
+    }
+}
+
+table! {
+    message_emotes (message_id, start_index) {
+        message_id -> Uuid,
+        emote_id -> Text,
+        start_index -> Int4,
+        end_index -> Int4,
+        name -> Text,
+    }
+}
+
+table! {
+    message_replies (message_id) {
+        message_id -> Uuid,
+        parent_msg_id -> Uuid,
+        parent_user_id -> Nullable<Text>,
+        parent_user_login -> Nullable<Text>,
+        parent_display_name -> Nullable<Text>,
+        parent_msg_body -> Nullable<Text>,
+        thread_parent_msg_id -> Nullable<Uuid>,
+        thread_parent_user_login -> Nullable<Text>,
+    }
+}
+
+table! {
+    messages (id) {
+        id -> Uuid,
+        badge_info -> Nullable<Text>,
+        badges -> Nullable<Jsonb>,
+        bits -> Nullable<Int4>,
+        color -> Nullable<Text>,
+        display_name -> Text,
+        emotes -> Nullable<Jsonb>,
+        #[sql_name = "mod"]
+        mod_ -> Nullable<Bool>,
+        room_id -> Int4,
+        tmi_sent_ts -> Timestamptz,
+        user_id -> Text,
+        channel -> Text,
+        message -> Text,
+        raw_message -> Text,
+        subscription_months -> Nullable<Int4>,
+        client_nonce -> Nullable<Text>,
+        emote_only -> Nullable<Bool>,
+        first_msg -> Nullable<Bool>,
+        flags -> Nullable<Text>,
+        returning_chatter -> Nullable<Bool>,
+        subscriber -> Nullable<Bool>,
+        turbo -> Nullable<Bool>,
+        user_type -> Nullable<Text>,
+        vip -> Nullable<Bool>,
+        other_tags -> Nullable<Jsonb>,
+        action -> Bool,
+    }
+}
+
+table! {
+    outages (id) {
+        id -> Int8,
+        channel -> Text,
+        started_at -> Int8,
+        ended_at -> Int8,
+        reason -> Text,
+    }
+}
+
+table! {
+    presence (id) {
+        id -> Int8,
+        channel -> Text,
+        login -> Text,
+        event -> Text,
+        received_at -> Int8,
+    }
+}
+
+table! {
+    room_states (id) {
+        id -> Int8,
+        channel -> Text,
+        room_id -> Int4,
+        emote_only -> Nullable<Bool>,
+        followers_only -> Nullable<Int4>,
+        r9k -> Nullable<Bool>,
+        slow -> Nullable<Int4>,
+        subs_only -> Nullable<Bool>,
+        received_at -> Int8,
+        raw_message -> Text,
+    }
+}
+
+table! {
+    user_notices (id) {
+        id -> Uuid,
+        channel -> Text,
+        room_id -> Int4,
+        msg_id -> Text,
+        user_id -> Nullable<Text>,
+        login -> Nullable<Text>,
+        display_name -> Nullable<Text>,
+        system_msg -> Nullable<Text>,
+        message -> Nullable<Text>,
+        params -> Jsonb,
+        tmi_sent_ts -> Timestamptz,
+        raw_message -> Text,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(
+    clear_chats,
+    clear_msgs,
+    message_badges,
+    message_emotes,
+    message_replies,
+    messages,
+    outages,
+    presence,
+    room_states,
+    user_notices,
+);
@@ -0,0 +1,712 @@
+use crate::error::MyError;
+use crate::joiner::{self, JoinCommand};
+use crate::sender::RecordSender;
+use crate::state::{ChannelState, ChannelTracker, Transition};
+use crate::twitchclient::{ClientConfig, IrcServer};
+use crate::types::{Outage, Presence, PresenceKind, Record};
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::stream::StreamExt as _;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use twitchchat::{events, messages, rate_limit::RateClass, Dispatcher, RateLimit, Runner, Status};
+use twitchchat::{Control, EventStream, UserConfig};
+
+pub type ConnectionId = usize;
+
+///Sent when the runner for a connection stops
+pub struct Closed {
+    pub id: ConnectionId,
+    pub status: Result<Status, twitchchat::Error>,
+    pub at: DateTime<Utc>,
+}
+
+impl Closed {
+    pub fn reason(&self) -> String {
+        match &self.status {
+            Ok(Status::Canceled) => "canceled".to_string(),
+            Ok(Status::Eof) => "eof".to_string(),
+            Ok(Status::Timeout) => "timeout".to_string(),
+            Err(err) => format!("error: {}", err),
+        }
+    }
+}
+
+///Things that happen to a connection that the pool needs to act on
+pub enum ConnectionEvent {
+    Closed(Closed),
+    ///The server is going away and asked us to reconnect
+    Reconnect(ConnectionId),
+    ///The server refused to let us into a channel, eg - because it's suspended
+    Unjoinable {
+        id: ConnectionId,
+        channel: String,
+        reason: String,
+    },
+    ///The server echoed our JOIN
+    Joined {
+        id: ConnectionId,
+        channel: String,
+    },
+    ///The server echoed our PART
+    Parted {
+        id: ConnectionId,
+        channel: String,
+    },
+    ///The server never confirmed a JOIN, even after retrying
+    JoinFailed {
+        id: ConnectionId,
+        channel: String,
+    },
+    ///Time to try joining a channel from a closed connection again
+    Rejoin(String),
+}
+
+//https://dev.twitch.tv/docs/irc/msg-id/
+//NOTICEs sent instead of a JOIN echo when a channel can't be joined
+const UNJOINABLE_MSG_IDS: &[&str] = &[
+    "msg_channel_suspended",
+    "msg_channel_blocked",
+    "msg_banned",
+    "tos_ban",
+];
+
+pub const DEFAULT_CHANNELS_PER_CONNECTION: usize = 50;
+pub const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(300);
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+///Exponential backoff between reconnection attempts
+struct Backoff {
+    next: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    fn new() -> Backoff {
+        Backoff {
+            next: MIN_RECONNECT_DELAY,
+            max: MAX_RECONNECT_DELAY,
+        }
+    }
+
+    fn delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = std::cmp::min(self.next * 2, self.max);
+        delay
+    }
+}
+
+struct Connection {
+    joiner: UnboundedSender<JoinCommand>,
+    control: Control,
+}
+
+impl Connection {
+    fn send(&self, command: JoinCommand) -> Result<(), MyError> {
+        self.joiner
+            .send(command)
+            .map_err(|_| MyError::Other("connection is closed".into()))
+    }
+}
+
+///A connection the server asked us to leave, kept open until its channels are joined on
+///another
+struct Draining {
+    conn: Connection,
+    waiting: HashSet<String>,
+}
+
+///Spreads joined channels over as many connections as needed so that no connection is in more
+///than `channels_per_connection` channels. Messages from every connection go to the same sender.
+///The state of each channel is kept in a `ChannelTracker` that can be shared with the caller.
+pub struct ConnectionPool {
+    connections: HashMap<ConnectionId, Connection>,
+    tracker: ChannelTracker,
+    next_id: ConnectionId,
+    config: ClientConfig,
+    sender: RecordSender,
+    events: UnboundedSender<ConnectionEvent>,
+    unjoinable: HashSet<String>,
+    ///When and why channels that were joined on a closed connection went missing, until they're
+    ///joined again
+    outages: HashMap<String, (DateTime<Utc>, String)>,
+    ///Channels that couldn't be queued on another connection and are waiting to be retried
+    retrying: HashMap<String, Backoff>,
+    draining: HashMap<ConnectionId, Draining>,
+}
+
+impl ConnectionPool {
+    ///Events from the receiver should be passed back to `handle`
+    pub fn new(
+        config: ClientConfig,
+        sender: RecordSender,
+        tracker: ChannelTracker,
+    ) -> (ConnectionPool, UnboundedReceiver<ConnectionEvent>) {
+        let (events, events_recv) = unbounded_channel();
+        let pool = ConnectionPool {
+            connections: HashMap::new(),
+            tracker,
+            next_id: 0,
+            config,
+            sender,
+            events,
+            unjoinable: HashSet::new(),
+            outages: HashMap::new(),
+            retrying: HashMap::new(),
+            draining: HashMap::new(),
+        };
+        (pool, events_recv)
+    }
+
+    ///Channels that are joined or being joined
+    pub fn joined(&self) -> HashSet<String> {
+        self.tracker.joined()
+    }
+
+    pub fn tracker(&self) -> &ChannelTracker {
+        &self.tracker
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    ///Channels the server has refused to let us join. These aren't retried.
+    pub fn unjoinable(&self) -> &HashSet<String> {
+        &self.unjoinable
+    }
+
+    pub async fn handle(&mut self, event: ConnectionEvent) {
+        match event {
+            ConnectionEvent::Closed(c) => {
+                eprintln!("[{}] connection {} closed: {}", c.at, c.id, c.reason());
+                self.reconnect(c).await;
+            }
+            ConnectionEvent::Reconnect(id) => {
+                eprintln!(
+                    "[{}] server asked connection {} to reconnect",
+                    Utc::now(),
+                    id
+                );
+                self.migrate(id).await;
+            }
+            ConnectionEvent::Unjoinable {
+                id,
+                channel,
+                reason,
+            } => {
+                eprintln!(
+                    "[{}] can't join {} on connection {}: {}",
+                    Utc::now(),
+                    channel,
+                    id,
+                    reason
+                );
+                if let Some(conn) = self.connections.get(&id) {
+                    let _ = conn.send(JoinCommand::Forget(channel.clone()));
+                }
+                self.tracker.apply(id, &channel, Transition::JoinFailed);
+                self.settle(&channel).await;
+                self.unjoinable.insert(channel);
+            }
+            ConnectionEvent::Joined { id, channel } => {
+                if self.tracker.apply(id, &channel, Transition::JoinEcho)
+                    == Some(ChannelState::Joined)
+                {
+                    self.settle(&channel).await;
+                }
+            }
+            ConnectionEvent::Parted { id, channel } => {
+                self.tracker.apply(id, &channel, Transition::PartEcho);
+            }
+            ConnectionEvent::JoinFailed { id, channel } => {
+                self.tracker.apply(id, &channel, Transition::JoinFailed);
+                self.settle(&channel).await;
+            }
+            ConnectionEvent::Rejoin(channel) => {
+                //it may have been parted while waiting
+                if self.retrying.contains_key(&channel) {
+                    self.rejoin(channel).await;
+                }
+            }
+        }
+    }
+
+    ///Queues a join on the least full connection, opening a new one if they are all full. The
+    ///JOIN is sent once the connection's rate limit allows.
+    pub async fn join(&mut self, channel: String) -> Result<(), MyError> {
+        //the server echoes channels in lowercase, which is how they're tracked
+        let channel = channel.to_lowercase();
+        if self.tracker.connection_of(&channel).is_some() {
+            return Ok(());
+        }
+        let id = match self.least_full() {
+            Some(id) => id,
+            None => self.open().await?,
+        };
+        self.connections[&id].send(JoinCommand::Join(channel.clone()))?;
+        self.tracker.pending(id, channel);
+        Ok(())
+    }
+
+    pub async fn part(&mut self, channel: &str) -> Result<(), MyError> {
+        let channel = &channel.to_lowercase();
+        self.retrying.remove(channel);
+        self.settle(channel).await;
+        if let Some(id) = self.tracker.connection_of(channel) {
+            if let Some(conn) = self.connections.get(&id) {
+                conn.send(JoinCommand::Part(channel.to_string()))?;
+            }
+            self.tracker.apply(id, channel, Transition::Part);
+        }
+        Ok(())
+    }
+
+    ///Keeps trying to join until it succeeds, waiting longer after each failure
+    pub async fn join_with_backoff(&mut self, channel: String) {
+        let channel = channel.to_lowercase();
+        if self.unjoinable.contains(&channel) {
+            return;
+        }
+        let mut backoff = Backoff::new();
+        while let Err(e) = self.join(channel.clone()).await {
+            let delay = backoff.delay();
+            eprintln!(
+                "[{}] error joining {}, retrying in {:?}: {}",
+                Utc::now(),
+                channel,
+                delay,
+                e
+            );
+            tokio::time::delay_for(delay).await;
+        }
+    }
+
+    ///Forgets a closed connection and queues its channels on the others (or a new connection).
+    ///The time each joined channel was missing for is sent on as an `Outage` once the server
+    ///confirms it's joined again.
+    pub async fn reconnect(&mut self, closed: Closed) {
+        let reason = closed.reason();
+        if let Some(draining) = self.draining.remove(&closed.id) {
+            //its channels are already queued elsewhere but will have missed messages
+            for c in draining.waiting {
+                self.outages
+                    .entry(c)
+                    .or_insert_with(|| (closed.at, reason.clone()));
+            }
+            return;
+        }
+        let conn = match self.connections.remove(&closed.id) {
+            Some(c) => c,
+            None => return,
+        };
+        conn.control.stop();
+        let channels = self.tracker.remove_connection(closed.id);
+        eprintln!(
+            "[{}] rejoining {} channels from closed connection {}",
+            Utc::now(),
+            channels.len(),
+            closed.id
+        );
+        for (c, state) in channels {
+            if !rejoin(state) {
+                continue;
+            }
+            if state == ChannelState::Joined {
+                //a channel that's still missing from an earlier outage keeps its start
+                self.outages
+                    .entry(c.clone())
+                    .or_insert_with(|| (closed.at, reason.clone()));
+            }
+            self.rejoin(c).await;
+        }
+    }
+
+    ///Moves all channels off a connection. It's kept open until each channel is joined on
+    ///another (or there's nothing more to wait for), so unlike `reconnect` nothing is missed.
+    async fn migrate(&mut self, id: ConnectionId) {
+        let conn = match self.connections.remove(&id) {
+            Some(c) => c,
+            None => return,
+        };
+        let mut waiting = HashSet::new();
+        for (c, state) in self.tracker.remove_connection(id) {
+            if rejoin(state) {
+                waiting.insert(c.clone());
+                self.rejoin(c).await;
+            }
+        }
+        //channels that were unjoinable aren't waited for
+        waiting.retain(|c| !self.unjoinable.contains(c));
+        if waiting.is_empty() {
+            conn.control.stop();
+        } else {
+            self.draining.insert(id, Draining { conn, waiting });
+        }
+    }
+
+    ///A channel has been joined again, failed or been parted. Its outage ends and connections
+    ///that were only being kept open for it are closed.
+    async fn settle(&mut self, channel: &str) {
+        self.end_outage(channel).await;
+        let mut drained = vec![];
+        for (id, draining) in self.draining.iter_mut() {
+            draining.waiting.remove(channel);
+            if draining.waiting.is_empty() {
+                drained.push(*id);
+            }
+        }
+        for id in drained {
+            eprintln!(
+                "[{}] closing connection {} now its channels have moved",
+                Utc::now(),
+                id
+            );
+            self.draining.remove(&id).unwrap().conn.control.stop();
+        }
+    }
+
+    ///Queues a join for a channel from a connection that's gone. If that fails it's tried again
+    ///later, with a `ConnectionEvent::Rejoin`, rather than holding up other events.
+    async fn rejoin(&mut self, channel: String) {
+        if self.unjoinable.contains(&channel) {
+            self.retrying.remove(&channel);
+            return;
+        }
+        let e = match self.join(channel.clone()).await {
+            Ok(()) => {
+                self.retrying.remove(&channel);
+                return;
+            }
+            Err(e) => e,
+        };
+        let delay = self
+            .retrying
+            .entry(channel.clone())
+            .or_insert_with(Backoff::new)
+            .delay();
+        eprintln!(
+            "[{}] error rejoining {}, retrying in {:?}: {}",
+            Utc::now(),
+            channel,
+            delay,
+            e
+        );
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            tokio::time::delay_for(delay).await;
+            let _ = events.send(ConnectionEvent::Rejoin(channel));
+        });
+    }
+
+    ///Sends on the outage for a channel if it went missing when its connection closed. It ends
+    ///when the channel is joined again, or when there's no longer anything to wait for.
+    async fn end_outage(&mut self, channel: &str) {
+        if let Some((start, reason)) = self.outages.remove(channel) {
+            let outage = Outage {
+                channel: format!("#{}", channel),
+                start,
+                end: Utc::now(),
+                reason,
+            };
+            let _ = self.sender.send(Record::Outage(outage)).await;
+        }
+    }
+
+    fn least_full(&self) -> Option<ConnectionId> {
+        self.connections
+            .keys()
+            .map(|id| (*id, self.tracker.load(*id)))
+            .filter(|(_, load)| *load < self.config.channels_per_connection)
+            .min_by_key(|(_, load)| *load)
+            .map(|(id, _)| id)
+    }
+
+    async fn open(&mut self) -> Result<ConnectionId, MyError> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let dispatcher = Dispatcher::new();
+        let mut ready = dispatcher.subscribe::<events::IrcReady>();
+        //records are built from the raw lines so the line can be stored with them
+        let raw = dispatcher.subscribe::<events::Raw>();
+        let notices = dispatcher.subscribe::<events::Notice>();
+        let reconnects = dispatcher.subscribe::<events::Reconnect>();
+        let joins = dispatcher.subscribe::<events::Join>();
+        let parts = dispatcher.subscribe::<events::Part>();
+        let presence = if self.config.capabilities.membership {
+            Some((
+                dispatcher.subscribe::<events::Join>(),
+                dispatcher.subscribe::<events::Part>(),
+            ))
+        } else {
+            None
+        };
+        let (runner, mut control) =
+            Runner::new(dispatcher, RateLimit::from_class(RateClass::Known));
+
+        let user_config = UserConfig::builder()
+            .anonymous()
+            .capabilities(&self.config.capabilities.list())
+            .build()
+            .unwrap();
+        // connect to twitch
+        //a server that never answers would otherwise hold up every other channel, so a timeout
+        //counts as a failed connect and is retried the same way
+        let timeout = self.config.connect_timeout;
+        let conn = tokio::time::timeout(timeout, connect(&self.config.server, &user_config))
+            .await
+            .map_err(|_| MyError::Other("timed out connecting".into()))??;
+        // and run the dispatcher/writer loop
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            let status = runner.run(conn).await;
+            let _ = events.send(ConnectionEvent::Closed(Closed {
+                id,
+                status,
+                at: Utc::now(),
+            }));
+        });
+        tokio::spawn(forward(raw, self.sender.clone()));
+        tokio::spawn(watch(id, notices, reconnects, self.events.clone()));
+
+        let ready = match tokio::time::timeout(timeout, ready.next()).await {
+            Ok(Some(ready)) => ready,
+            Ok(None) => {
+                return Err(MyError::Other(
+                    "connection closed before it was ready".into(),
+                ))
+            }
+            Err(_) => {
+                control.stop();
+                return Err(MyError::Other(
+                    "timed out waiting for the connection to be ready".into(),
+                ));
+            }
+        };
+        eprintln!("connection {} joined with nick {}", id, ready.nickname);
+
+        if let Some((joins, parts)) = presence {
+            tokio::spawn(forward_presence(
+                ready.nickname.to_string(),
+                joins,
+                parts,
+                self.sender.clone(),
+            ));
+        }
+        let (joiner, commands) = unbounded_channel();
+        tokio::spawn(joiner::run(
+            id,
+            ready.nickname.to_string(),
+            commands,
+            control.writer().clone(),
+            joins,
+            parts,
+            self.events.clone(),
+        ));
+        self.connections.insert(id, Connection { joiner, control });
+        Ok(id)
+    }
+}
+
+///Anything a connection can be run over
+trait IrcStream: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Sync + Unpin> IrcStream for T {}
+
+///Opens a connection to the server and logs in
+async fn connect(server: &IrcServer, user_config: &UserConfig) -> io::Result<Box<dyn IrcStream>> {
+    let stream = TcpStream::connect(&server.address).await?;
+    let mut stream: Box<dyn IrcStream> = if server.tls {
+        let connector: tokio_tls::TlsConnector = native_tls::TlsConnector::new()
+            .map_err(io::Error::other)?
+            .into();
+        Box::new(
+            connector
+                .connect(server.domain(), stream)
+                .await
+                .map_err(io::Error::other)?,
+        )
+    } else {
+        Box::new(stream)
+    };
+    twitchchat::register(user_config, &mut stream).await?;
+    Ok(stream)
+}
+
+///Whether a channel from a connection that's going away should be joined on another
+fn rejoin(state: ChannelState) -> bool {
+    state == ChannelState::PendingJoin || state == ChannelState::Joined
+}
+
+///Parses every line that's recorded and sends it on
+pub(crate) async fn forward(
+    mut events: EventStream<Arc<messages::Raw<'static>>>,
+    mut sender: RecordSender,
+) {
+    while let Some(msg) = events.next().await {
+        //the error isn't Send so can't be held over the await
+        let record = match Record::from_raw(&msg) {
+            Ok(Some(r)) => r,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("couldn't parse message: {}", e);
+                continue;
+            }
+        };
+        if sender.send(record).await.is_err() {
+            break;
+        }
+    }
+}
+
+///Sends on other users joining and leaving channels. Our own JOINs and PARTs are left to the
+///joiner.
+pub(crate) async fn forward_presence(
+    nick: String,
+    mut joins: EventStream<Arc<messages::Join<'static>>>,
+    mut parts: EventStream<Arc<messages::Part<'static>>>,
+    mut sender: RecordSender,
+) {
+    loop {
+        let (kind, channel, login) = tokio::select! {
+            Some(join) = joins.next() => (PresenceKind::Join, join.channel.to_string(), join.name.to_string()),
+            Some(part) = parts.next() => (PresenceKind::Part, part.channel.to_string(), part.name.to_string()),
+            else => break,
+        };
+        if login.eq_ignore_ascii_case(&nick) {
+            continue;
+        }
+        let presence = Presence {
+            channel,
+            login,
+            kind,
+            received_at: Utc::now(),
+        };
+        if sender.send(Record::Presence(presence)).await.is_err() {
+            break;
+        }
+    }
+}
+
+///Logs server notices and tells the pool about the ones it needs to act on
+async fn watch(
+    id: ConnectionId,
+    mut notices: EventStream<Arc<messages::Notice<'static>>>,
+    mut reconnects: EventStream<Arc<messages::Reconnect>>,
+    events: UnboundedSender<ConnectionEvent>,
+) {
+    loop {
+        let event = tokio::select! {
+            Some(notice) = notices.next() => {
+                eprintln!(
+                    "[{}] notice on connection {} in {}: {}",
+                    Utc::now(),
+                    id,
+                    notice.channel,
+                    notice.message
+                );
+                match unjoinable(&notice) {
+                    Some((channel, reason)) => ConnectionEvent::Unjoinable { id, channel, reason },
+                    None => continue,
+                }
+            }
+            Some(_) = reconnects.next() => ConnectionEvent::Reconnect(id),
+            else => break,
+        };
+        if events.send(event).is_err() {
+            break;
+        }
+    }
+}
+
+///The channel and msg-id if this notice means the channel can't be joined
+fn unjoinable(notice: &messages::Notice) -> Option<(String, String)> {
+    let msg_id = notice.tags.get("msg-id")?;
+    if UNJOINABLE_MSG_IDS.contains(&msg_id.as_ref()) {
+        let channel = notice.channel.trim_start_matches('#').to_string();
+        Some((channel, msg_id.to_string()))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+    use tokio::net::TcpListener;
+    use twitchchat::{AsOwned as _, Parse as _};
+
+    fn notice(raw: &str) -> messages::Notice<'static> {
+        let (_, msg) = twitchchat::decode_one(raw).unwrap();
+        messages::Notice::parse(&msg).unwrap().as_owned()
+    }
+
+    #[test]
+    fn test_unjoinable_notice() {
+        let n = notice(
+            "@msg-id=msg_channel_suspended :tmi.twitch.tv NOTICE #somechannel :This channel has been suspended.\r\n",
+        );
+        assert_eq!(
+            unjoinable(&n),
+            Some((
+                "somechannel".to_string(),
+                "msg_channel_suspended".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_other_notice_ignored() {
+        let n = notice(
+            "@msg-id=slow_on :tmi.twitch.tv NOTICE #somechannel :This room is now in slow mode.\r\n",
+        );
+        assert_eq!(unjoinable(&n), None);
+        let n = notice(":tmi.twitch.tv NOTICE * :Login unsuccessful\r\n");
+        assert_eq!(unjoinable(&n), None);
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new();
+        let delays: Vec<Duration> = (0..12).map(|_| backoff.delay()).collect();
+        assert_eq!(delays[0], MIN_RECONNECT_DELAY);
+        assert_eq!(delays[1], MIN_RECONNECT_DELAY * 2);
+        assert_eq!(delays[2], MIN_RECONNECT_DELAY * 4);
+        assert!(delays.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(*delays.last().unwrap(), MAX_RECONNECT_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_connect_times_out_without_a_greeting() {
+        //accepts connections but never says anything
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+        let config = ClientConfig {
+            server: IrcServer {
+                address,
+                tls: false,
+            },
+            connect_timeout: Duration::from_millis(100),
+            ..ClientConfig::default()
+        };
+        let (records, _records) = mpsc::channel();
+        let (mut pool, _events) =
+            ConnectionPool::new(config, records.into(), ChannelTracker::new());
+        assert!(pool.join("somechannel".to_string()).await.is_err());
+        assert_eq!(pool.connection_count(), 0);
+        assert!(pool.joined().is_empty());
+    }
+}
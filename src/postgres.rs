@@ -0,0 +1,553 @@
+use crate::db::CHILD_ROWS_PER_INSERT;
+use crate::db::{into_vec, MessageSink, Tables};
+use crate::error::MyError;
+use crate::models::{raw_line, tag_text, timestamp_to_millis};
+use crate::pg_schema::{
+    clear_chats, clear_msgs, message_badges, message_emotes, message_replies, messages, outages,
+    presence, room_states, user_notices,
+};
+use crate::types::{
+    Outage, Presence, Record, TwitchClearChat, TwitchClearMsg, TwitchMessage, TwitchRoomState,
+    TwitchUserNotice,
+};
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde_json::Value;
+use uuid::Uuid;
+
+///Writes to the tables created by the migrations in pg_migrations/. Unlike sqlite, ids, lists and
+///the timestamps twitch sends are stored with their own types. Times taken when a record is
+///collected are milliseconds since the epoch, the same as on sqlite.
+pub struct PgSink {
+    conn: PgConnection,
+}
+
+impl PgSink {
+    pub fn establish(database_url: &str) -> Result<PgSink, MyError> {
+        Ok(PgSink {
+            conn: PgConnection::establish(database_url)?,
+        })
+    }
+}
+
+impl MessageSink for PgSink {
+    ///Inserts each type of record into its own table. Messages that are already stored (eg -
+    ///seen on two connections while moving channels) are skipped.
+    fn write(&mut self, mut records: Vec<Record>) -> Result<usize, MyError> {
+        let conn = &self.conn;
+        let inserted = conn.transaction::<_, diesel::result::Error, _>(|| {
+            let mut inserted = 0;
+            while !records.is_empty() {
+                let rest = records.split_off(std::cmp::min(ROWS_PER_INSERT, records.len()));
+                inserted += insert_tables(conn, Tables::from(records))?;
+                records = rest;
+            }
+            Ok(inserted)
+        })?;
+        Ok(inserted)
+    }
+}
+
+//postgres allows 65535 bind parameters in a statement, and messages have 26 columns
+const ROWS_PER_INSERT: usize = 1024;
+
+fn insert_tables(conn: &PgConnection, tables: Tables) -> QueryResult<usize> {
+    insert_message_children(conn, &tables.messages)?;
+    Ok(diesel::insert_into(messages::table)
+        .values(into_vec::<_, PgMessage>(tables.messages))
+        .on_conflict_do_nothing()
+        .execute(conn)?
+        + diesel::insert_into(user_notices::table)
+            .values(into_vec::<_, PgUserNotice>(tables.user_notices))
+            .on_conflict_do_nothing()
+            .execute(conn)?
+        + diesel::insert_into(clear_chats::table)
+            .values(into_vec::<_, PgClearChat>(tables.clear_chats))
+            .execute(conn)?
+        + diesel::insert_into(clear_msgs::table)
+            .values(into_vec::<_, PgClearMsg>(tables.clear_msgs))
+            .execute(conn)?
+        + diesel::insert_into(room_states::table)
+            .values(into_vec::<_, PgRoomState>(tables.room_states))
+            .execute(conn)?
+        + diesel::insert_into(presence::table)
+            .values(into_vec::<_, PgPresence>(tables.presence))
+            .execute(conn)?
+        + diesel::insert_into(outages::table)
+            .values(into_vec::<_, PgOutage>(tables.outages))
+            .execute(conn)?)
+}
+
+///Inserts the badges, emotes and reply of each message into their own tables
+fn insert_message_children(conn: &PgConnection, messages: &[TwitchMessage]) -> QueryResult<()> {
+    let badges: Vec<PgMessageBadge> = messages
+        .iter()
+        .flat_map(PgMessageBadge::from_message)
+        .collect();
+    let emotes: Vec<PgMessageEmote> = messages
+        .iter()
+        .flat_map(PgMessageEmote::from_message)
+        .collect();
+    let replies: Vec<PgMessageReply> = messages
+        .iter()
+        .filter_map(PgMessageReply::from_message)
+        .collect();
+    for chunk in badges.chunks(CHILD_ROWS_PER_INSERT) {
+        diesel::insert_into(message_badges::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    for chunk in emotes.chunks(CHILD_ROWS_PER_INSERT) {
+        diesel::insert_into(message_emotes::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    for chunk in replies.chunks(CHILD_ROWS_PER_INSERT) {
+        diesel::insert_into(message_replies::table)
+            .values(chunk)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+///Replaces everything stored from the tags of messages that are already in the database, eg -
+///after parsing their raw lines again. Messages are matched by id.
+pub(crate) fn rewrite_messages(
+    conn: &PgConnection,
+    messages: Vec<TwitchMessage>,
+) -> QueryResult<()> {
+    let ids: Vec<Uuid> = messages.iter().map(|m| m.tags.id).collect();
+    for chunk in ids.chunks(CHILD_ROWS_PER_INSERT) {
+        diesel::delete(message_badges::table.filter(message_badges::message_id.eq_any(chunk)))
+            .execute(conn)?;
+        diesel::delete(message_emotes::table.filter(message_emotes::message_id.eq_any(chunk)))
+            .execute(conn)?;
+        diesel::delete(message_replies::table.filter(message_replies::message_id.eq_any(chunk)))
+            .execute(conn)?;
+    }
+    insert_message_children(conn, &messages)?;
+    for message in messages {
+        let message = PgMessage::from(message);
+        diesel::update(messages::table.find(message.id))
+            .set(&message)
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+#[derive(Insertable, AsChangeset)]
+#[table_name = "messages"]
+#[changeset_options(treat_none_as_null = "true")]
+struct PgMessage {
+    id: Uuid,
+    badge_info: Option<String>,
+    badges: Option<Value>,
+    bits: Option<i32>,
+    color: Option<String>,
+    display_name: String,
+    emotes: Option<Value>,
+    mod_: Option<bool>,
+    room_id: i32,
+    tmi_sent_ts: DateTime<Utc>,
+    user_id: String,
+    channel: String,
+    message: String,
+    raw_message: String,
+    subscription_months: Option<i32>,
+    client_nonce: Option<String>,
+    emote_only: Option<bool>,
+    first_msg: Option<bool>,
+    flags: Option<String>,
+    returning_chatter: Option<bool>,
+    subscriber: Option<bool>,
+    turbo: Option<bool>,
+    user_type: Option<String>,
+    vip: Option<bool>,
+    other_tags: Option<Value>,
+    action: bool,
+}
+
+impl From<TwitchMessage> for PgMessage {
+    fn from(message: TwitchMessage) -> Self {
+        PgMessage {
+            id: message.tags.id,
+            subscription_months: message.tags.subscription_months(),
+            badge_info: message.tags.badge_info.map(|b| tag_text(&b)),
+            badges: message.tags.badges.map(tag_json),
+            bits: message.tags.bits,
+            color: message.tags.color,
+            display_name: message.tags.display_name,
+            emotes: message.tags.emotes.map(tag_json),
+            mod_: message.tags.moderator,
+            room_id: message.tags.room_id,
+            tmi_sent_ts: message.tags.tmi_sent_ts,
+            user_id: message.tags.user_id,
+            channel: message.channel,
+            message: message.message,
+            raw_message: raw_line(&message.raw),
+            client_nonce: message.tags.client_nonce,
+            emote_only: message.tags.emote_only,
+            first_msg: message.tags.first_msg,
+            flags: message.tags.flags.map(|f| tag_text(&f)),
+            returning_chatter: message.tags.returning_chatter,
+            subscriber: message.tags.subscriber,
+            turbo: message.tags.turbo,
+            user_type: message.tags.user_type,
+            vip: message.tags.vip,
+            other_tags: Some(message.tags.other)
+                .filter(|o| !o.is_empty())
+                .map(|o| o.into_iter().map(|(k, v)| (k, Value::from(v))).collect()),
+            action: message.action,
+        }
+    }
+}
+
+///Badges and emotes columns hold the tag text of each one
+fn tag_json<T: ToString>(v: Vec<T>) -> Value {
+    v.iter().map(T::to_string).collect()
+}
+
+#[derive(Insertable)]
+#[table_name = "message_replies"]
+struct PgMessageReply {
+    message_id: Uuid,
+    parent_msg_id: Uuid,
+    parent_user_id: Option<String>,
+    parent_user_login: Option<String>,
+    parent_display_name: Option<String>,
+    parent_msg_body: Option<String>,
+    thread_parent_msg_id: Option<Uuid>,
+    thread_parent_user_login: Option<String>,
+}
+
+impl PgMessageReply {
+    fn from_message(message: &TwitchMessage) -> Option<PgMessageReply> {
+        let reply = message.tags.reply.as_deref()?.clone();
+        Some(PgMessageReply {
+            message_id: message.tags.id,
+            parent_msg_id: reply.parent_msg_id,
+            parent_user_id: reply.parent_user_id,
+            parent_user_login: reply.parent_user_login,
+            parent_display_name: reply.parent_display_name,
+            parent_msg_body: reply.parent_msg_body,
+            thread_parent_msg_id: reply.thread_parent_msg_id,
+            thread_parent_user_login: reply.thread_parent_user_login,
+        })
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "message_badges"]
+struct PgMessageBadge {
+    message_id: Uuid,
+    name: String,
+    version: String,
+}
+
+impl PgMessageBadge {
+    fn from_message(message: &TwitchMessage) -> Vec<PgMessageBadge> {
+        message
+            .tags
+            .badges
+            .iter()
+            .flatten()
+            .map(|b| PgMessageBadge {
+                message_id: message.tags.id,
+                name: b.name.clone(),
+                version: b.version.clone(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "message_emotes"]
+struct PgMessageEmote {
+    message_id: Uuid,
+    emote_id: String,
+    start_index: i32,
+    end_index: i32,
+    name: String,
+}
+
+impl PgMessageEmote {
+    fn from_message(message: &TwitchMessage) -> Vec<PgMessageEmote> {
+        message
+            .tags
+            .emotes
+            .iter()
+            .flatten()
+            .flat_map(|e| {
+                e.ranges.iter().map(move |r| PgMessageEmote {
+                    message_id: message.tags.id,
+                    emote_id: e.emote_id.clone(),
+                    start_index: r.start as i32,
+                    end_index: r.end as i32,
+                    name: r.text(&message.message).unwrap_or_default().to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "user_notices"]
+struct PgUserNotice {
+    id: Uuid,
+    channel: String,
+    room_id: i32,
+    msg_id: String,
+    user_id: Option<String>,
+    login: Option<String>,
+    display_name: Option<String>,
+    system_msg: Option<String>,
+    message: Option<String>,
+    params: Value,
+    tmi_sent_ts: DateTime<Utc>,
+    raw_message: String,
+}
+
+impl From<TwitchUserNotice> for PgUserNotice {
+    fn from(notice: TwitchUserNotice) -> Self {
+        PgUserNotice {
+            id: notice.id,
+            channel: notice.channel,
+            room_id: notice.room_id,
+            msg_id: notice.msg_id,
+            user_id: notice.user_id,
+            login: notice.login,
+            display_name: notice.display_name,
+            system_msg: notice.system_msg,
+            message: notice.message,
+            params: serde_json::to_value(notice.params).unwrap_or_default(),
+            tmi_sent_ts: notice.tmi_sent_ts,
+            raw_message: raw_line(&notice.raw),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "clear_chats"]
+struct PgClearChat {
+    channel: String,
+    room_id: i32,
+    target_login: Option<String>,
+    target_user_id: Option<String>,
+    ban_duration: Option<i32>,
+    tmi_sent_ts: DateTime<Utc>,
+    raw_message: String,
+}
+
+impl From<TwitchClearChat> for PgClearChat {
+    fn from(clear: TwitchClearChat) -> Self {
+        PgClearChat {
+            channel: clear.channel,
+            room_id: clear.room_id,
+            target_login: clear.target_login,
+            target_user_id: clear.target_user_id,
+            ban_duration: clear.ban_duration,
+            tmi_sent_ts: clear.tmi_sent_ts,
+            raw_message: raw_line(&clear.raw),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "clear_msgs"]
+struct PgClearMsg {
+    channel: String,
+    login: Option<String>,
+    target_msg_id: Uuid,
+    message: Option<String>,
+    tmi_sent_ts: DateTime<Utc>,
+    raw_message: String,
+}
+
+impl From<TwitchClearMsg> for PgClearMsg {
+    fn from(clear: TwitchClearMsg) -> Self {
+        PgClearMsg {
+            channel: clear.channel,
+            login: clear.login,
+            target_msg_id: clear.target_msg_id,
+            message: clear.message,
+            tmi_sent_ts: clear.tmi_sent_ts,
+            raw_message: raw_line(&clear.raw),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "room_states"]
+struct PgRoomState {
+    channel: String,
+    room_id: i32,
+    emote_only: Option<bool>,
+    followers_only: Option<i32>,
+    r9k: Option<bool>,
+    slow: Option<i32>,
+    subs_only: Option<bool>,
+    received_at: i64,
+    raw_message: String,
+}
+
+impl From<TwitchRoomState> for PgRoomState {
+    fn from(state: TwitchRoomState) -> Self {
+        PgRoomState {
+            channel: state.channel,
+            room_id: state.room_id,
+            emote_only: state.emote_only,
+            followers_only: state.followers_only,
+            r9k: state.r9k,
+            slow: state.slow,
+            subs_only: state.subs_only,
+            received_at: timestamp_to_millis(state.received_at),
+            raw_message: raw_line(&state.raw),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "presence"]
+struct PgPresence {
+    channel: String,
+    login: String,
+    event: String,
+    received_at: i64,
+}
+
+impl From<Presence> for PgPresence {
+    fn from(presence: Presence) -> Self {
+        PgPresence {
+            channel: presence.channel,
+            login: presence.login,
+            event: presence.kind.as_str().to_string(),
+            received_at: timestamp_to_millis(presence.received_at),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "outages"]
+struct PgOutage {
+    channel: String,
+    started_at: i64,
+    ended_at: i64,
+    reason: String,
+}
+
+impl From<Outage> for PgOutage {
+    fn from(outage: Outage) -> Self {
+        PgOutage {
+            channel: outage.channel,
+            started_at: timestamp_to_millis(outage.start),
+            ended_at: timestamp_to_millis(outage.end),
+            reason: outage.reason,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::samples;
+    use diesel::connection::SimpleConnection;
+    use diesel::dsl::count_star;
+
+    //needs a local postgres, eg - TEST_DATABASE_URL=postgres://localhost/chat_test
+    #[test]
+    #[ignore]
+    fn test_pg_sink_writes_every_table() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL not set");
+        let mut sink = PgSink::establish(&url).unwrap();
+        for down in &[
+            include_str!("../pg_migrations/2026-10-18-190000_action_messages/down.sql"),
+            include_str!("../pg_migrations/2026-10-18-180000_privmsg_tags/down.sql"),
+            include_str!("../pg_migrations/2026-10-18-160000_emote_names/down.sql"),
+            include_str!("../pg_migrations/2026-10-18-150000_badges_emotes/down.sql"),
+            include_str!("../pg_migrations/2026-10-18-120000_init/down.sql"),
+        ] {
+            let _ = sink.conn.batch_execute(down);
+        }
+        crate::db::run_migrations(&sink.conn, "pg_migrations");
+
+        assert_eq!(sink.write(samples::records()).unwrap(), 7);
+        //duplicate messages are ignored
+        assert_eq!(sink.write(samples::records()).unwrap(), 5);
+        let reply = || vec![Record::Message(samples::reply())];
+        assert_eq!(sink.write(reply()).unwrap(), 1);
+        assert_eq!(sink.write(reply()).unwrap(), 0);
+
+        let conn = &sink.conn;
+        let messages: i64 = messages::table.select(count_star()).first(conn).unwrap();
+        assert_eq!(messages, 2);
+        let ts: DateTime<Utc> = messages::table
+            .select(messages::tmi_sent_ts)
+            .order(messages::tmi_sent_ts)
+            .first(conn)
+            .unwrap();
+        assert_eq!(ts.timestamp_millis(), 1507246572675);
+        let sample = messages::id.eq(samples::message().tags.id);
+        let badges: Option<Value> = messages::table
+            .filter(sample)
+            .select(messages::badges)
+            .first(conn)
+            .unwrap();
+        assert_eq!(badges.unwrap()[0], "subscriber/6");
+        let months: Option<i32> = messages::table
+            .filter(sample)
+            .select(messages::subscription_months)
+            .first(conn)
+            .unwrap();
+        assert_eq!(months, Some(8));
+        let outages: i64 = outages::table.select(count_star()).first(conn).unwrap();
+        assert_eq!(outages, 2);
+        let emotes: i64 = message_emotes::table
+            .select(count_star())
+            .first(conn)
+            .unwrap();
+        assert_eq!(emotes, 3);
+        let keepo: i64 = message_emotes::table
+            .filter(message_emotes::name.eq("Keepo"))
+            .select(count_star())
+            .first(conn)
+            .unwrap();
+        assert_eq!(keepo, 1);
+        let parent: Uuid = message_replies::table
+            .select(message_replies::parent_msg_id)
+            .first(conn)
+            .unwrap();
+        assert_eq!(parent, samples::message().tags.id);
+        let other: Option<Value> = messages::table
+            .filter(messages::id.eq(samples::reply().tags.id))
+            .select(messages::other_tags)
+            .first(conn)
+            .unwrap();
+        assert_eq!(other.unwrap()["pinned-chat-paid-amount"], "500");
+
+        assert_eq!(
+            sink.write(vec![Record::Message(samples::action())])
+                .unwrap(),
+            1
+        );
+        let action: (String, bool) = messages::table
+            .filter(messages::id.eq(samples::action().tags.id))
+            .select((messages::message, messages::action))
+            .first(&sink.conn)
+            .unwrap();
+        assert_eq!(action, ("waves Kappa".to_string(), true));
+
+        //more rows than fit in one statement's bind parameters
+        let many: Vec<Record> = (0..5000u128)
+            .map(|i| {
+                let mut message = samples::message();
+                message.tags.id = Uuid::from_bytes(i.to_be_bytes());
+                Record::Message(message)
+            })
+            .collect();
+        assert_eq!(sink.write(many).unwrap(), 5000);
+    }
+}
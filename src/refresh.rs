@@ -0,0 +1,149 @@
+use crate::channels::{self, Helix};
+use chrono::Utc;
+use std::collections::HashSet;
+use std::iter::FromIterator;
+use std::time::Duration;
+
+///How to keep the joined channels following the top live streams
+#[derive(Clone)]
+pub struct RefreshConfig {
+    pub helix: Helix,
+    pub max_channels: u64,
+    pub interval: Duration,
+}
+
+///Channels to join and part to bring the joined set up to date
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ChannelChanges {
+    pub join: Vec<String>,
+    pub part: Vec<String>,
+}
+
+///There are 3 passes over the joined channels:
+///1. Mark channels not returned in API to be left
+///2. for the channels returned by the API, swap out a channel to be left for a fresh one
+///3. If the API happens to return a higher (closer to expected) number of channels than it did
+///   last time then join these too
+pub fn plan_refresh(
+    joined: &HashSet<String>,
+    top_channels: Vec<String>,
+    max_channels: usize,
+) -> ChannelChanges {
+    let top_set: HashSet<&String> = HashSet::from_iter(top_channels.iter());
+    let to_leave = joined.iter().filter(|c| !top_set.contains(c));
+
+    let mut changes = ChannelChanges::default();
+    let mut fresh = top_channels.iter().filter(|c| !joined.contains(*c));
+    //because of the API issues there can be fewer fresh channels than channels to leave, in
+    //which case the old ones are kept rather than leaving the slot empty
+    for leaving in to_leave {
+        match fresh.next() {
+            Some(c) => {
+                changes.join.push(c.to_string());
+                changes.part.push(leaving.to_string());
+            }
+            None => break,
+        }
+    }
+
+    let room = max_channels.saturating_sub(joined.len());
+    changes.join.extend(fresh.take(room).cloned());
+    changes
+}
+
+///Refetches the top channels and works out how to follow them, ignoring any that can't be
+///joined. Returns None if the API didn't return any channels.
+pub(crate) async fn fetch_changes(
+    refresh: &RefreshConfig,
+    joined: &HashSet<String>,
+    unjoinable: &HashSet<String>,
+) -> Option<ChannelChanges> {
+    let raw_channels = channels::top_connections(&refresh.helix, refresh.max_channels).await;
+    let mut top_channels = channels::cleanup_channels(raw_channels, refresh.max_channels);
+    top_channels.retain(|c| !unjoinable.contains(c));
+    if top_channels.is_empty() {
+        eprintln!(
+            "[{}] API returned no channels, skipping refresh",
+            Utc::now()
+        );
+        return None;
+    }
+    Some(plan_refresh(
+        joined,
+        top_channels,
+        refresh.max_channels as usize,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(range: std::ops::Range<u32>) -> Vec<String> {
+        range.map(|i| i.to_string()).collect()
+    }
+
+    fn apply(joined: &mut HashSet<String>, changes: ChannelChanges) {
+        for c in changes.join {
+            assert!(joined.insert(c), "joined a channel twice");
+        }
+        for c in changes.part {
+            assert!(joined.remove(&c), "parted a channel that wasn't joined");
+        }
+    }
+
+    //TODO might be nice to have a property test here
+    fn assert_refresh_works(initial: Vec<String>, final_channels: Vec<String>) {
+        let max = final_channels.len();
+        let mut joined = HashSet::from_iter(initial);
+        let changes = plan_refresh(&joined, final_channels.clone(), max);
+        apply(&mut joined, changes);
+        assert_eq!(HashSet::from_iter(final_channels), joined);
+    }
+
+    #[test]
+    fn test_refresh_channels_no_op() {
+        let channels = names(0..10);
+        let joined = HashSet::from_iter(channels.clone());
+        assert_eq!(
+            plan_refresh(&joined, channels.clone(), 10),
+            ChannelChanges::default()
+        );
+        assert_refresh_works(channels.clone(), channels);
+    }
+
+    #[test]
+    fn test_refresh_channels_single_replacement() {
+        let channels = names(0..100);
+        let mut new_channels = channels.clone();
+        new_channels.pop();
+        new_channels.push("101".to_string());
+        assert_eq!(channels.len(), new_channels.len());
+        assert_refresh_works(channels, new_channels);
+    }
+
+    #[test]
+    fn test_channels_replace_all() {
+        let channels = names(0..100);
+        let new_channels = names(300..400);
+        assert_eq!(channels.len(), new_channels.len());
+        assert_refresh_works(channels, new_channels);
+    }
+
+    #[test]
+    fn test_refresh_fills_up_to_max() {
+        assert_refresh_works(names(0..50), names(0..100));
+    }
+
+    #[test]
+    fn simulate_api_issues_refresh() {
+        //API returns fewer channels than requested, old channels are kept to fill the gap
+        let mut joined = HashSet::from_iter(names(0..100));
+        let changes = plan_refresh(&joined, names(10..101), 100);
+        assert_eq!(changes.join, vec!["100".to_string()]);
+        assert_eq!(changes.part.len(), 1);
+        apply(&mut joined, changes);
+        assert_eq!(joined.len(), 100);
+        assert!(names(10..101).iter().all(|c| joined.contains(c)));
+    }
+}
@@ -0,0 +1,280 @@
+use crate::db::{self, is_postgres_url};
+use crate::error::MyError;
+use crate::models::{uuid_from_bytes, uuid_to_bytes};
+use crate::postgres;
+use crate::types::TwitchMessage;
+use chrono::Utc;
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use std::convert::TryFrom;
+use uuid::Uuid;
+
+///How a reparse went
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReparseStats {
+    pub reparsed: usize,
+    ///Rows whose raw_message couldn't be parsed, eg - ones stored before the IRC line was kept.
+    ///They're left as they are.
+    pub skipped: usize,
+}
+
+///Parses the raw_message of every row of the messages table again and rewrites the columns and
+///tables that come from it, `batch_size` rows per transaction. Progress is saved with each batch
+///so an interrupted run carries on where it stopped, unless `restart` is set.
+pub fn reparse_messages(
+    database_url: &str,
+    batch_size: usize,
+    restart: bool,
+) -> Result<ReparseStats, MyError> {
+    if is_postgres_url(database_url) {
+        let conn = PgConnection::establish(database_url)?;
+        reparse(&conn, batch_size, restart)
+    } else {
+        let conn = SqliteConnection::establish(database_url)?;
+        reparse(&conn, batch_size, restart)
+    }
+}
+
+fn reparse<C: Reparse>(
+    conn: &C,
+    batch_size: usize,
+    restart: bool,
+) -> Result<ReparseStats, MyError> {
+    if restart {
+        conn.clear_position()?;
+    }
+    let total = conn.count()?;
+    let mut last = conn.position()?;
+    let mut stats = ReparseStats::default();
+    if let Some(id) = last {
+        println!("[{}] resuming reparse after message {}", Utc::now(), id);
+    }
+    loop {
+        let page = conn.page(last, batch_size as i64)?;
+        let (id, _) = match page.last() {
+            Some(row) => row,
+            None => break,
+        };
+        last = Some(*id);
+        let mut messages = Vec::with_capacity(page.len());
+        for (id, raw) in &page {
+            match parse(raw) {
+                Some(mut message) => {
+                    //rows are updated by the id they're stored with
+                    message.tags.id = *id;
+                    messages.push(message);
+                }
+                None => stats.skipped += 1,
+            }
+        }
+        stats.reparsed += messages.len();
+        conn.rewrite(messages, *id)?;
+        println!(
+            "[{}] reparsed {} of {} messages, {} skipped",
+            Utc::now(),
+            stats.reparsed,
+            total,
+            stats.skipped
+        );
+    }
+    conn.clear_position()?;
+    Ok(stats)
+}
+
+///A raw_message as a message, or None if it isn't a PRIVMSG line this parser understands
+fn parse(raw: &str) -> Option<TwitchMessage> {
+    let line = format!("{}\r\n", raw);
+    let (_, msg) = twitchchat::decode_one(&line).ok()?;
+    TwitchMessage::try_from(&msg).ok()
+}
+
+///A database whose messages can be reparsed. The position is the id of the last message
+///rewritten, kept in a reparse_progress table until the reparse finishes.
+trait Reparse {
+    fn count(&self) -> Result<i64, MyError>;
+
+    ///Ids and raw lines of the messages after `after`, in id order
+    fn page(&self, after: Option<Uuid>, limit: i64) -> Result<Vec<(Uuid, String)>, MyError>;
+
+    ///Rewrites the messages and saves the position in one transaction
+    fn rewrite(&self, messages: Vec<TwitchMessage>, position: Uuid) -> Result<(), MyError>;
+
+    fn position(&self) -> Result<Option<Uuid>, MyError>;
+
+    fn clear_position(&self) -> Result<(), MyError>;
+}
+
+impl Reparse for SqliteConnection {
+    fn count(&self) -> Result<i64, MyError> {
+        use crate::schema::messages;
+        Ok(messages::table.count().get_result(self)?)
+    }
+
+    fn page(&self, after: Option<Uuid>, limit: i64) -> Result<Vec<(Uuid, String)>, MyError> {
+        use crate::schema::messages;
+        let after = after.map(uuid_to_bytes).unwrap_or_default();
+        let rows: Vec<(Vec<u8>, String)> = messages::table
+            .select((messages::id, messages::raw_message))
+            .filter(messages::id.gt(after))
+            .order(messages::id)
+            .limit(limit)
+            .load(self)?;
+        rows.into_iter()
+            .map(|(id, raw)| Ok((uuid_from_bytes(&id)?, raw)))
+            .collect()
+    }
+
+    fn rewrite(&self, messages: Vec<TwitchMessage>, position: Uuid) -> Result<(), MyError> {
+        use diesel::sql_types::Binary;
+        self.transaction::<_, diesel::result::Error, _>(|| {
+            db::rewrite_messages(self, messages)?;
+            self.batch_execute(
+                "CREATE TABLE IF NOT EXISTS reparse_progress (last_id BLOB NOT NULL); \
+                 DELETE FROM reparse_progress;",
+            )?;
+            diesel::sql_query("INSERT INTO reparse_progress (last_id) VALUES (?)")
+                .bind::<Binary, _>(uuid_to_bytes(position))
+                .execute(self)?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    fn position(&self) -> Result<Option<Uuid>, MyError> {
+        self.batch_execute("CREATE TABLE IF NOT EXISTS reparse_progress (last_id BLOB NOT NULL)")?;
+        let id: Option<Vec<u8>> = diesel::dsl::sql("SELECT last_id FROM reparse_progress")
+            .get_result(self)
+            .optional()?;
+        id.map(|id| uuid_from_bytes(&id)).transpose()
+    }
+
+    fn clear_position(&self) -> Result<(), MyError> {
+        self.batch_execute("DROP TABLE IF EXISTS reparse_progress")?;
+        Ok(())
+    }
+}
+
+impl Reparse for PgConnection {
+    fn count(&self) -> Result<i64, MyError> {
+        use crate::pg_schema::messages;
+        Ok(messages::table.count().get_result(self)?)
+    }
+
+    fn page(&self, after: Option<Uuid>, limit: i64) -> Result<Vec<(Uuid, String)>, MyError> {
+        use crate::pg_schema::messages;
+        Ok(messages::table
+            .select((messages::id, messages::raw_message))
+            .filter(messages::id.gt(after.unwrap_or_else(Uuid::nil)))
+            .order(messages::id)
+            .limit(limit)
+            .load(self)?)
+    }
+
+    fn rewrite(&self, messages: Vec<TwitchMessage>, position: Uuid) -> Result<(), MyError> {
+        use diesel::sql_types::Uuid as SqlUuid;
+        self.transaction::<_, diesel::result::Error, _>(|| {
+            postgres::rewrite_messages(self, messages)?;
+            self.batch_execute(
+                "CREATE TABLE IF NOT EXISTS reparse_progress (last_id UUID NOT NULL); \
+                 DELETE FROM reparse_progress;",
+            )?;
+            diesel::sql_query("INSERT INTO reparse_progress (last_id) VALUES ($1)")
+                .bind::<SqlUuid, _>(position)
+                .execute(self)?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    fn position(&self) -> Result<Option<Uuid>, MyError> {
+        self.batch_execute("CREATE TABLE IF NOT EXISTS reparse_progress (last_id UUID NOT NULL)")?;
+        Ok(diesel::dsl::sql("SELECT last_id FROM reparse_progress")
+            .get_result(self)
+            .optional()?)
+    }
+
+    fn clear_position(&self) -> Result<(), MyError> {
+        self.batch_execute("DROP TABLE IF EXISTS reparse_progress")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::run_migrations;
+    use crate::models::Message;
+    use crate::schema::{message_badges, message_replies, messages};
+    use crate::types::samples;
+
+    //the reply sorts before the sample message by id. Neither has its badges or reply stored, as
+    //if they were from an older parser.
+    fn database() -> SqliteConnection {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        run_migrations(&conn, "migrations");
+        let mut rows = vec![
+            Message::from(samples::message()),
+            Message::from(samples::reply()),
+        ];
+        for row in &mut rows {
+            row.subscription_months = None;
+            row.first_msg = None;
+        }
+        diesel::insert_into(messages::table)
+            .values(&rows)
+            .execute(&conn)
+            .unwrap();
+        conn.batch_execute(
+            "INSERT INTO messages (id, display_name, room_id, tmi_sent_ts, user_id, channel, \
+             message, raw_message) VALUES (x'ffffffffffffffffffffffffffffffff', 'A', 1, 0, '1', \
+             '#c', 'hi', 'Privmsg { name: \"a\" }');",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_reparse_rewrites_derived_columns() {
+        let conn = &database();
+        let stats = reparse(conn, 1, false).unwrap();
+        assert_eq!(
+            stats,
+            ReparseStats {
+                reparsed: 2,
+                skipped: 1
+            }
+        );
+        let months: Vec<Option<i32>> = messages::table
+            .select(messages::subscription_months)
+            .order(messages::id)
+            .load(conn)
+            .unwrap();
+        assert_eq!(months, vec![None, Some(8), None]);
+        let badges: i64 = message_badges::table.count().get_result(conn).unwrap();
+        assert_eq!(badges, 3);
+        let replies: i64 = message_replies::table.count().get_result(conn).unwrap();
+        assert_eq!(replies, 1);
+        //nothing is left to resume
+        assert_eq!(conn.position().unwrap(), None);
+    }
+
+    #[test]
+    fn test_reparse_resumes() {
+        let conn = &database();
+        //as if a run stopped after the first batch
+        conn.rewrite(vec![], samples::reply().tags.id).unwrap();
+        assert_eq!(conn.position().unwrap(), Some(samples::reply().tags.id));
+        let stats = reparse(conn, 10, false).unwrap();
+        assert_eq!(stats.reparsed, 1);
+        let first_msg: Option<bool> = messages::table
+            .select(messages::first_msg)
+            .filter(messages::id.eq(uuid_to_bytes(samples::reply().tags.id)))
+            .first(conn)
+            .unwrap();
+        assert_eq!(first_msg, None);
+
+        conn.rewrite(vec![], samples::reply().tags.id).unwrap();
+        assert_eq!(reparse(conn, 10, true).unwrap().reparsed, 2);
+    }
+}
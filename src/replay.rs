@@ -0,0 +1,246 @@
+use crate::error::MyError;
+use crate::pool::{forward, forward_presence};
+use crate::sender::RecordSender;
+use chrono::Utc;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::stream::StreamExt;
+use tokio::time::{self, Instant};
+use twitchchat::{events, Dispatcher, RateLimit, Runner, Status};
+
+///How quickly lines are replayed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    ///Keep the gaps between lines' tmi-sent-ts, divided by this. Lines without one are sent
+    ///straight after the line before.
+    Timed(f64),
+    ///As fast as they can be read
+    Unlimited,
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = MyError;
+
+    ///eg - 1 for the original timing, 10 for ten times as fast, or max
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "max" {
+            return Ok(ReplaySpeed::Unlimited);
+        }
+        match s.parse::<f64>() {
+            Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(ReplaySpeed::Timed(speed)),
+            _ => Err(MyError::Config(format!(
+                "replay speed must be a positive number or max, not {}",
+                s
+            ))),
+        }
+    }
+}
+
+///A file of raw IRC lines to replay, eg - the raw_message column of the messages table
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayConfig {
+    pub path: PathBuf,
+    pub speed: ReplaySpeed,
+}
+
+///Sends every line of the file through a twitchchat dispatcher, the same as lines from twitch,
+///and the records parsed from them to `sender`. Returns the number of lines replayed once
+///they've all been parsed.
+pub async fn replay_messages(config: ReplayConfig, sender: RecordSender) -> Result<usize, MyError> {
+    let file = File::open(&config.path)
+        .map_err(|e| MyError::Config(format!("could not open {}: {}", config.path.display(), e)))?;
+
+    let dispatcher = Dispatcher::new();
+    let raw = dispatcher.subscribe::<events::Raw>();
+    let joins = dispatcher.subscribe::<events::Join>();
+    let parts = dispatcher.subscribe::<events::Part>();
+    let (runner, _control) = Runner::new(dispatcher, RateLimit::default());
+
+    //the runner reads from a connection, so the lines are written to it over loopback
+    let mut listener = TcpListener::bind("127.0.0.1:0").await?;
+    let client = TcpStream::connect(listener.local_addr()?).await?;
+    let (server, _) = listener.accept().await?;
+
+    let started = std::time::Instant::now();
+    let feeder = tokio::spawn(feed(file, config.speed, server));
+    let records = tokio::spawn(forward(raw, sender.clone()));
+    //there's no nick of our own to leave out
+    let presence = tokio::spawn(forward_presence(String::new(), joins, parts, sender));
+    match runner.run(client).await {
+        Ok(Status::Eof) => {}
+        Ok(status) => eprintln!("[{}] replay stopped early: {:?}", Utc::now(), status),
+        Err(e) => return Err(MyError::Other(Box::new(e))),
+    }
+
+    let lines = feeder.await.map_err(|e| MyError::Other(Box::new(e)))??;
+    //the streams end once the runner is gone, after the last record is sent on
+    let _ = records.await;
+    let _ = presence.await;
+    eprintln!(
+        "[{}] replayed {} lines in {:.1}s",
+        Utc::now(),
+        lines,
+        started.elapsed().as_secs_f64()
+    );
+    Ok(lines)
+}
+
+///Writes each line of the file to the connection, waiting between them depending on the speed
+async fn feed(file: File, speed: ReplaySpeed, server: TcpStream) -> io::Result<usize> {
+    let (read, mut write) = server.into_split();
+    let mut pings = tokio::io::BufReader::new(read).lines();
+    let mut pacing = Pacing::new(speed);
+    let mut lines = 0;
+    //a blocking read, but it's a local file and buffered
+    for line in BufReader::new(file).lines() {
+        //lines() takes off the \r\n as well as \n
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(due) = pacing.due(&line) {
+            //the runner pings if it hears nothing for a while, so answer like twitch would
+            loop {
+                tokio::select! {
+                    _ = time::delay_until(due) => break,
+                    Some(Ok(ping)) = pings.next() => {
+                        if let Some(token) = ping.strip_prefix("PING ") {
+                            let pong = format!(":tmi.twitch.tv PONG tmi.twitch.tv {}\r\n", token);
+                            write.write_all(pong.as_bytes()).await?;
+                        }
+                    }
+                }
+            }
+        }
+        write.write_all(line.as_bytes()).await?;
+        write.write_all(b"\r\n").await?;
+        lines += 1;
+    }
+    //closing the connection stops the runner
+    write.shutdown().await?;
+    Ok(lines)
+}
+
+///When to send each line so they're as far apart as when they were received
+struct Pacing {
+    speed: ReplaySpeed,
+    ///The first tmi-sent-ts and when its line was sent
+    start: Option<(i64, Instant)>,
+}
+
+impl Pacing {
+    fn new(speed: ReplaySpeed) -> Pacing {
+        Pacing { speed, start: None }
+    }
+
+    ///When the line should be sent, or None for straight away
+    fn due(&mut self, line: &str) -> Option<Instant> {
+        let speed = match self.speed {
+            ReplaySpeed::Timed(speed) => speed,
+            ReplaySpeed::Unlimited => return None,
+        };
+        let ts = tmi_sent_ts(line)?;
+        let (first, started) = *self.start.get_or_insert((ts, Instant::now()));
+        let millis = (ts - first).max(0) as f64 / speed;
+        Some(started + Duration::from_micros((millis * 1000.0) as u64))
+    }
+}
+
+///The tmi-sent-ts tag of a line, in milliseconds
+fn tmi_sent_ts(line: &str) -> Option<i64> {
+    let tags = line.strip_prefix('@')?.split(' ').next()?;
+    tags.split(';')
+        .find_map(|tag| tag.strip_prefix("tmi-sent-ts="))?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::{samples, Record};
+    use tokio::sync::mpsc;
+
+    fn replay_file(name: &str, lines: &[&str]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("replay-{}-{}", std::process::id(), name));
+        std::fs::write(&path, lines.concat()).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_replay_parses_every_line() {
+        let path = replay_file(
+            "all",
+            &[
+                samples::PRIVMSG,
+                samples::USERNOTICE,
+                "PING :tmi.twitch.tv\r\n",
+                "\n",
+                samples::CLEARCHAT,
+                //stored raw messages don't end in \r\n
+                samples::REPLY.trim_end(),
+                "\n",
+                ":viewer!viewer@viewer.tmi.twitch.tv JOIN #dallas\r\n",
+            ],
+        );
+        let (tx, mut rx) = mpsc::channel(16);
+        let config = ReplayConfig {
+            path: path.clone(),
+            speed: ReplaySpeed::Unlimited,
+        };
+        assert_eq!(replay_messages(config, tx.into()).await.unwrap(), 6);
+
+        let mut records = vec![];
+        while let Some(r) = rx.recv().await {
+            records.push(r);
+        }
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[0], Record::Message(samples::message()));
+        assert!(records
+            .iter()
+            .any(|r| matches!(r, Record::Presence(p) if p.login == "viewer")));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_keeps_timing() {
+        //the reply was sent 27 seconds after the message, so 10ms at 2700x
+        let path = replay_file("timed", &[samples::PRIVMSG, samples::REPLY]);
+        let (tx, _rx) = mpsc::channel(16);
+        let config = ReplayConfig {
+            path: path.clone(),
+            speed: ReplaySpeed::Timed(2700.0),
+        };
+        let started = std::time::Instant::now();
+        replay_messages(config, tx.into()).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(10));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_pacing() {
+        let mut pacing = Pacing::new(ReplaySpeed::Timed(2.0));
+        assert!(pacing.due("PING :tmi.twitch.tv").is_none());
+        let first = pacing.due(samples::PRIVMSG).unwrap();
+        let second = pacing.due(samples::REPLY).unwrap();
+        assert_eq!(second - first, Duration::from_micros(27_325_000 / 2));
+        assert!(Pacing::new(ReplaySpeed::Unlimited)
+            .due(samples::PRIVMSG)
+            .is_none());
+
+        assert_eq!(
+            "max".parse::<ReplaySpeed>().unwrap(),
+            ReplaySpeed::Unlimited
+        );
+        assert_eq!(
+            "10".parse::<ReplaySpeed>().unwrap(),
+            ReplaySpeed::Timed(10.0)
+        );
+        assert!("0".parse::<ReplaySpeed>().is_err());
+    }
+}
@@ -1,20 +1,152 @@
 table! {
     messages (id) {
-        id -> Nullable<Text>,
+        id -> Binary,
         badge_info -> Nullable<Text>,
         badges -> Nullable<Text>,
         bits -> Nullable<Integer>,
         color -> Nullable<Text>,
-        display_name -> Nullable<Text>,
+        display_name -> Text,
         emotes -> Nullable<Text>,
-        message_id -> Nullable<Text>,
         #[sql_name = "mod"]
         mod_ -> Nullable<Bool>,
-        room_id -> Nullable<Integer>,
-        tmi_sent_ts -> Nullable<Timestamp>,
+        room_id -> Integer,
+        tmi_sent_ts -> BigInt,
+        user_id -> Text,
+        channel -> Text,
+        message -> Text,
+        raw_message -> Text,
+        subscription_months -> Nullable<Integer>,
+        client_nonce -> Nullable<Text>,
+        emote_only -> Nullable<Bool>,
+        first_msg -> Nullable<Bool>,
+        flags -> Nullable<Text>,
+        returning_chatter -> Nullable<Bool>,
+        subscriber -> Nullable<Bool>,
+        turbo -> Nullable<Bool>,
+        user_type -> Nullable<Text>,
+        vip -> Nullable<Bool>,
+        other_tags -> Nullable<Text>,
+        action -> Bool,
+    }
+}
+
+table! {
+    message_badges (message_id, name) {
+        message_id -> Binary,
+        name -> Text,
+        version -> Text,
+    }
+}
+
+table! {
+    message_emotes (message_id, start_index) {
+        message_id -> Binary,
+        emote_id -> Text,
+        start_index -> Integer,
+        end_index -> Integer,
+        name -> Text,
+    }
+}
+
+table! {
+    message_replies (message_id) {
+        message_id -> Binary,
+        parent_msg_id -> Binary,
+        parent_user_id -> Nullable<Text>,
+        parent_user_login -> Nullable<Text>,
+        parent_display_name -> Nullable<Text>,
+        parent_msg_body -> Nullable<Text>,
+        thread_parent_msg_id -> Nullable<Binary>,
+        thread_parent_user_login -> Nullable<Text>,
+    }
+}
+
+table! {
+    outages (id) {
+        id -> Integer,
+        channel -> Text,
+        started_at -> BigInt,
+        ended_at -> BigInt,
+        reason -> Text,
+    }
+}
+
+table! {
+    user_notices (id) {
+        id -> Binary,
+        channel -> Text,
+        room_id -> Integer,
+        msg_id -> Text,
         user_id -> Nullable<Text>,
-        channel -> Nullable<Text>,
+        login -> Nullable<Text>,
+        display_name -> Nullable<Text>,
+        system_msg -> Nullable<Text>,
         message -> Nullable<Text>,
-        raw_message -> Nullable<Text>,
+        params -> Text,
+        tmi_sent_ts -> BigInt,
+        raw_message -> Text,
+    }
+}
+
+table! {
+    clear_chats (id) {
+        id -> Integer,
+        channel -> Text,
+        room_id -> Integer,
+        target_login -> Nullable<Text>,
+        target_user_id -> Nullable<Text>,
+        ban_duration -> Nullable<Integer>,
+        tmi_sent_ts -> BigInt,
+        raw_message -> Text,
+    }
+}
+
+table! {
+    clear_msgs (id) {
+        id -> Integer,
+        channel -> Text,
+        login -> Nullable<Text>,
+        target_msg_id -> Binary,
+        message -> Nullable<Text>,
+        tmi_sent_ts -> BigInt,
+        raw_message -> Text,
+    }
+}
+
+table! {
+    presence (id) {
+        id -> Integer,
+        channel -> Text,
+        login -> Text,
+        event -> Text,
+        received_at -> BigInt,
+    }
+}
+
+table! {
+    room_states (id) {
+        id -> Integer,
+        channel -> Text,
+        room_id -> Integer,
+        emote_only -> Nullable<Bool>,
+        followers_only -> Nullable<Integer>,
+        r9k -> Nullable<Bool>,
+        slow -> Nullable<Integer>,
+        subs_only -> Nullable<Bool>,
+        received_at -> BigInt,
+        raw_message -> Text,
     }
 }
+
+allow_tables_to_appear_in_same_query!(
+    clear_chats,
+    clear_msgs,
+    message_badges,
+    message_emotes,
+    message_replies,
+    messages,
+    outages,
+    presence,
+    room_states,
+    user_notices,
+);
@@ -0,0 +1,89 @@
+use crate::types::{Record, TwitchMessage};
+use futures::stream::{Stream, StreamExt};
+use std::sync::mpsc;
+use tokio::sync::mpsc as async_mpsc;
+
+///Where collected records are sent. The database writer runs on its own thread so takes them from
+///an unbounded std channel, callers in an async context can instead use a bounded channel, in
+///which case collection waits for them to catch up.
+#[derive(Clone)]
+pub enum RecordSender {
+    Unbounded(mpsc::Sender<Record>),
+    Bounded(async_mpsc::Sender<Record>),
+}
+
+///The receiving end has gone away
+#[derive(Debug)]
+pub struct Closed;
+
+impl RecordSender {
+    pub async fn send(&mut self, record: Record) -> Result<(), Closed> {
+        match self {
+            RecordSender::Unbounded(s) => s.send(record).map_err(|_| Closed),
+            RecordSender::Bounded(s) => s.send(record).await.map_err(|_| Closed),
+        }
+    }
+}
+
+impl From<mpsc::Sender<Record>> for RecordSender {
+    fn from(sender: mpsc::Sender<Record>) -> Self {
+        RecordSender::Unbounded(sender)
+    }
+}
+
+impl From<async_mpsc::Sender<Record>> for RecordSender {
+    fn from(sender: async_mpsc::Sender<Record>) -> Self {
+        RecordSender::Bounded(sender)
+    }
+}
+
+///Just the chat messages from a stream of records
+pub fn messages<S>(records: S) -> impl Stream<Item = TwitchMessage>
+where
+    S: Stream<Item = Record>,
+{
+    records.filter_map(|r| async move {
+        match r {
+            Record::Message(m) => Some(m),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::samples;
+
+    #[tokio::test]
+    async fn test_messages_only() {
+        let (tx, rx) = async_mpsc::channel(16);
+        let mut sender = RecordSender::from(tx);
+        for r in samples::records() {
+            sender.send(r).await.unwrap();
+        }
+        drop(sender);
+        let messages: Vec<TwitchMessage> = messages(rx).collect().await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].tags.id, samples::message().tags.id);
+    }
+
+    #[tokio::test]
+    async fn test_closed_receiver() {
+        let (tx, rx) = async_mpsc::channel(1);
+        drop(rx);
+        let mut sender = RecordSender::from(tx);
+        assert!(sender
+            .send(Record::Message(samples::message()))
+            .await
+            .is_err());
+
+        let (tx, rx) = mpsc::channel();
+        drop(rx);
+        let mut sender = RecordSender::from(tx);
+        assert!(sender
+            .send(Record::Message(samples::message()))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use crate::pool::ConnectionId;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+///Where a channel is in the process of being joined or left on a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum ChannelState {
+    ///JOIN queued or sent, waiting for the server to echo it back
+    PendingJoin,
+    Joined,
+    ///PART sent, waiting for the server to echo it back
+    Parting,
+    ///The server never confirmed the JOIN or refused it
+    Failed,
+}
+
+///Things that move a channel between states
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    JoinEcho,
+    JoinFailed,
+    Part,
+    PartEcho,
+}
+
+impl ChannelState {
+    ///The state after `transition`, or None if the channel should be forgotten. Transitions that
+    ///don't make sense in the current state (eg - a late JOIN echo while parting) are ignored.
+    pub fn next(self, transition: Transition) -> Option<ChannelState> {
+        use ChannelState::*;
+        use Transition::*;
+        match (self, transition) {
+            (PendingJoin, JoinEcho) => Some(Joined),
+            (PendingJoin, JoinFailed) => Some(Failed),
+            //the JOIN may not have been sent yet so there won't be a PART echo to wait for
+            (PendingJoin, Part) => None,
+            (Joined, Part) => Some(Parting),
+            //the server removed us from the channel without being asked
+            (Joined, PartEcho) => None,
+            (Parting, PartEcho) | (Parting, JoinFailed) => None,
+            (Failed, Part) => None,
+            (state, _) => Some(state),
+        }
+    }
+
+    ///Whether the channel takes up a slot on its connection
+    pub fn is_active(self) -> bool {
+        self != ChannelState::Failed
+    }
+}
+
+///Number of channels in each state, for status reporting
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct StateCounts {
+    pub pending_join: usize,
+    pub joined: usize,
+    pub parting: usize,
+    pub failed: usize,
+}
+
+impl fmt::Display for StateCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} joined, {} pending, {} parting, {} failed",
+            self.joined, self.pending_join, self.parting, self.failed
+        )
+    }
+}
+
+type States = HashMap<ConnectionId, HashMap<String, ChannelState>>;
+
+///The state of every channel on every connection in a pool. Cloning gives another handle to the
+///same states, so it can be kept by the caller to query while the collector runs.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelTracker {
+    states: Arc<RwLock<States>>,
+}
+
+impl ChannelTracker {
+    pub fn new() -> ChannelTracker {
+        ChannelTracker::default()
+    }
+
+    ///Every connection's channels and their states
+    pub fn snapshot(&self) -> States {
+        self.states.read().unwrap().clone()
+    }
+
+    ///The state of a channel on whichever connection it's on. If it's on more than one (eg -
+    ///while moving between connections) the active one is preferred.
+    pub fn state(&self, channel: &str) -> Option<ChannelState> {
+        let states = self.states.read().unwrap();
+        states
+            .values()
+            .filter_map(|c| c.get(channel).copied())
+            .max_by_key(|s| s.is_active())
+    }
+
+    pub fn channels_in(&self, state: ChannelState) -> HashSet<String> {
+        self.filtered(|s| s == state)
+    }
+
+    ///Channels that are joined or being joined
+    pub fn joined(&self) -> HashSet<String> {
+        self.filtered(|s| s == ChannelState::PendingJoin || s == ChannelState::Joined)
+    }
+
+    pub fn counts(&self) -> StateCounts {
+        let mut counts = StateCounts::default();
+        for state in self
+            .states
+            .read()
+            .unwrap()
+            .values()
+            .flat_map(|c| c.values())
+        {
+            match state {
+                ChannelState::PendingJoin => counts.pending_join += 1,
+                ChannelState::Joined => counts.joined += 1,
+                ChannelState::Parting => counts.parting += 1,
+                ChannelState::Failed => counts.failed += 1,
+            }
+        }
+        counts
+    }
+
+    fn filtered(&self, keep: impl Fn(ChannelState) -> bool) -> HashSet<String> {
+        let states = self.states.read().unwrap();
+        states
+            .values()
+            .flat_map(|c| c.iter())
+            .filter(|(_, s)| keep(**s))
+            .map(|(c, _)| c.to_string())
+            .collect()
+    }
+
+    ///Number of channels taking up slots on a connection
+    pub(crate) fn load(&self, id: ConnectionId) -> usize {
+        self.states
+            .read()
+            .unwrap()
+            .get(&id)
+            .map(|c| c.values().filter(|s| s.is_active()).count())
+            .unwrap_or(0)
+    }
+
+    ///The connection a channel is joined or being joined on
+    pub(crate) fn connection_of(&self, channel: &str) -> Option<ConnectionId> {
+        let states = self.states.read().unwrap();
+        states
+            .iter()
+            .find(|(_, c)| {
+                matches!(
+                    c.get(channel),
+                    Some(ChannelState::PendingJoin) | Some(ChannelState::Joined)
+                )
+            })
+            .map(|(id, _)| *id)
+    }
+
+    ///Starts tracking a channel as pending on a connection, forgetting any earlier failures
+    pub(crate) fn pending(&self, id: ConnectionId, channel: String) {
+        let mut states = self.states.write().unwrap();
+        for c in states.values_mut() {
+            if c.get(&channel) == Some(&ChannelState::Failed) {
+                c.remove(&channel);
+            }
+        }
+        states
+            .entry(id)
+            .or_default()
+            .insert(channel, ChannelState::PendingJoin);
+    }
+
+    ///Applies a transition to a channel on a connection, returning its new state. Untracked
+    ///channels are ignored.
+    pub(crate) fn apply(
+        &self,
+        id: ConnectionId,
+        channel: &str,
+        transition: Transition,
+    ) -> Option<ChannelState> {
+        let mut states = self.states.write().unwrap();
+        let conn = states.get_mut(&id)?;
+        let next = conn.get(channel)?.next(transition);
+        match next {
+            Some(s) => conn.insert(channel.to_string(), s),
+            None => conn.remove(channel),
+        };
+        next
+    }
+
+    ///Forgets a connection, returning the states its channels were in
+    pub(crate) fn remove_connection(&self, id: ConnectionId) -> HashMap<String, ChannelState> {
+        self.states.write().unwrap().remove(&id).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ChannelState::*;
+    use Transition::*;
+
+    #[test]
+    fn test_join_then_part() {
+        let tracker = ChannelTracker::new();
+        tracker.pending(0, "a".to_string());
+        assert_eq!(tracker.state("a"), Some(PendingJoin));
+        assert_eq!(tracker.apply(0, "a", JoinEcho), Some(Joined));
+        assert_eq!(tracker.joined().len(), 1);
+        assert_eq!(tracker.apply(0, "a", Part), Some(Parting));
+        assert!(tracker.joined().is_empty());
+        assert_eq!(tracker.load(0), 1);
+        assert_eq!(tracker.apply(0, "a", PartEcho), None);
+        assert_eq!(tracker.state("a"), None);
+        assert_eq!(tracker.load(0), 0);
+    }
+
+    #[test]
+    fn test_failed_channels_are_replaced_by_new_joins() {
+        let tracker = ChannelTracker::new();
+        tracker.pending(0, "a".to_string());
+        assert_eq!(tracker.apply(0, "a", JoinFailed), Some(Failed));
+        assert_eq!(tracker.load(0), 0);
+        assert_eq!(tracker.connection_of("a"), None);
+        assert_eq!(tracker.counts().failed, 1);
+
+        tracker.pending(1, "a".to_string());
+        assert_eq!(tracker.connection_of("a"), Some(1));
+        assert_eq!(
+            tracker.counts(),
+            StateCounts {
+                pending_join: 1,
+                ..StateCounts::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_out_of_order_echoes_ignored() {
+        assert_eq!(Parting.next(JoinEcho), Some(Parting));
+        assert_eq!(PendingJoin.next(PartEcho), Some(PendingJoin));
+        assert_eq!(Failed.next(JoinEcho), Some(Failed));
+
+        let tracker = ChannelTracker::new();
+        assert_eq!(tracker.apply(0, "a", JoinEcho), None);
+        assert!(tracker.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_remove_connection() {
+        let tracker = ChannelTracker::new();
+        tracker.pending(0, "a".to_string());
+        tracker.pending(0, "b".to_string());
+        tracker.apply(0, "b", JoinEcho);
+        tracker.pending(1, "c".to_string());
+        let removed = tracker.remove_connection(0);
+        assert_eq!(removed.get("a"), Some(&PendingJoin));
+        assert_eq!(removed.get("b"), Some(&Joined));
+        assert_eq!(tracker.joined().len(), 1);
+    }
+}
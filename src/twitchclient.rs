@@ -1,86 +1,154 @@
-use crate::types::TwitchMessage;
-use std::convert::TryFrom;
-use std::sync::mpsc;
-use std::sync::Arc;
-use tokio::stream::StreamExt as _;
-use twitchchat::{
-    events, messages, rate_limit::RateClass, Dispatcher, IntoChannel, RateLimit, Runner, Status,
-};
-use twitchchat::{Capability, EventStream, UserConfig};
+use crate::error::MyError;
+use crate::pool::{ConnectionPool, CONNECT_TIMEOUT, DEFAULT_CHANNELS_PER_CONNECTION};
+use crate::refresh::{self, RefreshConfig};
+use crate::sender::RecordSender;
+use crate::state::ChannelTracker;
+use chrono::Utc;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::{self, Instant, Interval};
+use twitchchat::{Capability, TWITCH_IRC_ADDRESS_TLS};
 
-async fn setup(dispatcher: Dispatcher) -> EventStream<Arc<messages::Privmsg<'static>>> {
-    let events = dispatcher.subscribe::<events::Privmsg>();
+///Settings for the IRC connections
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub channels_per_connection: usize,
+    pub capabilities: Capabilities,
+    pub server: IrcServer,
+    ///How long to wait for a new connection to be ready before giving up on it
+    pub connect_timeout: Duration,
+}
 
-    let ready = dispatcher.wait_for::<events::IrcReady>().await.unwrap();
-    eprintln!("joined with nick {}", ready.nickname);
-    events
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            channels_per_connection: DEFAULT_CHANNELS_PER_CONNECTION,
+            capabilities: Capabilities::default(),
+            server: IrcServer::default(),
+            connect_timeout: CONNECT_TIMEOUT,
+        }
+    }
 }
 
-async fn run(dispatcher: Dispatcher, sender: mpsc::Sender<TwitchMessage>) {
-    let mut events = setup(dispatcher).await;
+///Where the IRC connections go. Twitch's server unless testing.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct IrcServer {
+    ///host:port
+    pub address: String,
+    pub tls: bool,
+}
 
-    while let Some(msg) = events.next().await {
-        sender.send(TwitchMessage::try_from(msg).unwrap()).unwrap();
+impl Default for IrcServer {
+    fn default() -> Self {
+        IrcServer {
+            address: TWITCH_IRC_ADDRESS_TLS.to_string(),
+            tls: true,
+        }
     }
 }
 
-pub async fn get_messages(
-    channels: Vec<impl IntoChannel + std::fmt::Display + std::clone::Clone + Send + Sync + 'static>,
-    sender: mpsc::Sender<TwitchMessage>,
-) {
-    let dispatcher = Dispatcher::new();
-    let (runner, mut control) =
-        Runner::new(dispatcher.clone(), RateLimit::from_class(RateClass::Known));
-    let mut writer = control.writer().clone();
+impl IrcServer {
+    ///The host the server's certificate should be for
+    pub fn domain(&self) -> &str {
+        self.address
+            .rsplit_once(':')
+            .map_or(&self.address, |(host, _)| host)
+    }
+}
+
+///Optional IRC capabilities to request. Tags are always requested since messages can't be parsed
+///without them.
+///https://dev.twitch.tv/docs/irc/capabilities
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Capabilities {
+    ///USERNOTICE, CLEARCHAT, CLEARMSG and ROOMSTATE
+    pub commands: bool,
+    ///JOIN and PART for other users, stored as viewer presence
+    pub membership: bool,
+}
 
-    let user_config = UserConfig::builder()
-        .anonymous()
-        .capabilities(&[Capability::Tags])
-        .build()
-        .unwrap();
-    // connect to twitch
-    let conn = twitchchat::connect_tls(&user_config).await.unwrap();
-    // and run the dispatcher/writer loop
-    let done = runner.run(conn);
+impl Default for Capabilities {
+    fn default() -> Self {
+        Capabilities {
+            commands: true,
+            membership: false,
+        }
+    }
+}
 
-    //    let printer = tokio::spawn(async move {
-    //     let mut counter = 0u64;
-    //     while let Some(msg) = comm_recv.next().await {
-    //         counter += 1;
-    // //           if (counter % 1) == 0 {
-    // //               eprintln!("received {} messages", counter);
-    // //           }
-    // //           TODO parsing here is probably awful for performance?
-    //         println!("received a {}th message {:?}",counter, TwitchMessage::try_from(msg));
+impl Capabilities {
+    pub fn list(self) -> Vec<Capability> {
+        let mut caps = vec![Capability::Tags];
+        if self.commands {
+            caps.push(Capability::Commands);
+        }
+        if self.membership {
+            caps.push(Capability::Membership);
+        }
+        caps
+    }
+}
 
-    //     }
-    // });
+pub async fn get_messages(
+    channels: Vec<String>,
+    sender: RecordSender,
+    config: ClientConfig,
+    refresh: Option<RefreshConfig>,
+    tracker: ChannelTracker,
+) -> Result<(), MyError> {
+    let (mut pool, mut events) = ConnectionPool::new(config, sender, tracker);
 
-    let message_receiver = tokio::spawn(async move {
-        run(dispatcher, sender).await;
-    });
+    eprintln!("joining {} channels", channels.len());
+    for c in channels {
+        pool.join_with_backoff(c).await;
+    }
+    eprintln!(
+        "queued joins for {} channels on {} connections",
+        pool.joined().len(),
+        pool.connection_count()
+    );
 
-    let joiner = tokio::spawn(async move {
-        eprintln!("joining channels");
-        //TODO need to try and join channels concurrently
-        for c in channels {
-            writer.join(c.clone()).await.unwrap();
-            eprintln!("joined {}", c);
-        }
-        eprintln!("done joining channels");
-    });
-    tokio::select! {
-        _ = joiner => { eprintln!("joiner task crashed") }
-        // wait for the bot to complete
-        _ = message_receiver => { eprintln!("done running the bot") }
-        // or wait for the runner to complete
-        status = done => {
-            match status {
-                Ok(Status::Canceled) => { eprintln!("runner was canceled") }
-                Ok(Status::Eof) => { eprintln!("got an eof, exiting") }
-                Ok(Status::Timeout) => { eprintln!("client connection timed out") }
-                Err(err) => { eprintln!("error running: {}", err) }
+    let mut timer = refresh
+        .as_ref()
+        .map(|r| time::interval_at(Instant::now() + r.interval, r.interval));
+    loop {
+        tokio::select! {
+            Some(event) = events.recv() => pool.handle(event).await,
+            _ = tick(&mut timer) => {
+                let refresh = refresh.as_ref().unwrap();
+                if let Some(changes) = refresh::fetch_changes(refresh, &pool.joined(), pool.unjoinable()).await {
+                    //join before parting so there's never a gap in the number of channels
+                    for c in &changes.join {
+                        if let Err(e) = pool.join(c.to_string()).await {
+                            eprintln!("error joining {}: {}", c, e);
+                        }
+                    }
+                    for c in &changes.part {
+                        if let Err(e) = pool.part(c).await {
+                            eprintln!("error parting {}: {}", c, e);
+                        }
+                    }
+                    eprintln!(
+                        "[{}] refreshed channels: joining {}, parting {}, now {} on {} connections",
+                        Utc::now(),
+                        changes.join.len(),
+                        changes.part.len(),
+                        pool.tracker().counts(),
+                        pool.connection_count()
+                    );
+                }
             }
         }
     }
 }
+
+async fn tick(timer: &mut Option<Interval>) {
+    match timer {
+        Some(t) => {
+            t.tick().await;
+        }
+        None => futures::future::pending().await,
+    }
+}
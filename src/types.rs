@@ -9,28 +9,81 @@ use std::time::{Duration, UNIX_EPOCH};
 
 use uuid::Uuid;
 
-use std::sync::Arc;
-use twitchchat::{messages::Privmsg, Tags};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use twitchchat::messages::{ClearChat, ClearMsg, Privmsg, Raw, RoomState, UserNotice};
+use twitchchat::{Parse, Tags};
 //https://dev.twitch.tv/docs/irc/tags/#privmsg-twitch-tags
-//deprecated tags not serialised
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub struct TwitchTags {
-    pub badge_info: Option<String>,
-    pub badges: Option<Vec<String>>,
+    ///Extra detail about some badges, eg - subscriber/14 is the exact number of months subscribed
+    pub badge_info: Option<Vec<Badge>>,
+    pub badges: Option<Vec<Badge>>,
     pub bits: Option<i32>,
+    ///Set by the sender's client, eg - to match up messages it sent
+    pub client_nonce: Option<String>,
 
     //https://en.wikipedia.org/wiki/Web_colors
     pub color: Option<String>,
     pub display_name: String,
-    pub emotes: Option<Vec<String>>,
+    ///The message is only emotes
+    pub emote_only: Option<bool>,
+    pub emotes: Option<Vec<EmoteUsage>>,
+    ///The user's first message in the channel
+    pub first_msg: Option<bool>,
+    ///Parts of the message AutoMod flagged
+    pub flags: Option<Vec<AutoModFlag>>,
     pub id: Uuid,
     pub moderator: Option<bool>,
+    ///The message it's replying to, if it's a reply
+    pub reply: Option<Box<Reply>>,
+    ///A returning chatter, as twitch decides it
+    pub returning_chatter: Option<bool>,
     pub room_id: i32,
+    //subscriber, turbo and user_type are deprecated in favour of badges but are still sent
+    pub subscriber: Option<bool>,
     pub tmi_sent_ts: DateTime<Utc>,
+    pub turbo: Option<bool>,
     pub user_id: String,
+    ///eg - mod, global_mod, admin, staff. Empty for normal users.
+    pub user_type: Option<String>,
+    pub vip: Option<bool>,
+    ///Tags not listed above, so ones twitch adds later aren't lost
+    #[serde(default)]
+    pub other: BTreeMap<String, String>,
 }
 
-//TODO recheck if there's any interesting fields to add to this
+//every tag read into a field of TwitchTags
+const PRIVMSG_TAGS: &[&str] = &[
+    "badge-info",
+    "badges",
+    "bits",
+    "client-nonce",
+    "color",
+    "display-name",
+    "emote-only",
+    "emotes",
+    "first-msg",
+    "flags",
+    "id",
+    "mod",
+    "reply-parent-display-name",
+    "reply-parent-msg-body",
+    "reply-parent-msg-id",
+    "reply-parent-user-id",
+    "reply-parent-user-login",
+    "reply-thread-parent-msg-id",
+    "reply-thread-parent-user-login",
+    "returning-chatter",
+    "room-id",
+    "subscriber",
+    "tmi-sent-ts",
+    "turbo",
+    "user-id",
+    "user-type",
+    "vip",
+];
 
 impl TryFrom<Tags<'_>> for TwitchTags {
     type Error = MyError;
@@ -38,58 +91,853 @@ impl TryFrom<Tags<'_>> for TwitchTags {
     //TODO errors as always
     fn try_from(tags: Tags) -> Result<Self, Self::Error> {
         //TODO can probably just use turbofish type for this instead of separate param
+        let badge_info: Option<String> = tags.get_parsed("badge-info");
         let badges: Option<String> = tags.get_parsed("badges");
         let emotes: Option<String> = tags.get_parsed("emotes");
+        let flags: Option<String> = tags.get_parsed("flags");
         let uuidstr: Option<String> = tags.get_parsed("id");
         let timestr: Option<String> = tags.get_parsed("tmi-sent-ts");
+        let flag = |key: &str| tags.get_parsed::<_, u8>(key).map(|v| v != 0);
+        let other = tags
+            .iter()
+            .filter(|(k, _)| !PRIVMSG_TAGS.contains(&k.as_ref()))
+            .map(|(k, v)| (k.to_string(), unescape_tag(v)))
+            .collect();
         Ok(TwitchTags {
-            badge_info: tags.get_parsed("badge-info"),
-            badges: badges.map(|s| s.split(',').map(String::from).collect()),
+            badge_info: badge_info.map(|s| parse_list(&s, ',')).transpose()?,
+            badges: badges.map(|s| parse_list(&s, ',')).transpose()?,
             bits: tags.get_parsed("bits"),
+            client_nonce: tags.get_parsed("client-nonce"),
             color: tags.get_parsed("color"), //https://en.wikipedia.org/wiki/Web_colors
             display_name: tags
                 .get_parsed("display-name")
                 .ok_or(MyError::Parse("Display name not present"))?,
-            emotes: emotes.map(|s| s.split('/').map(String::from).collect()),
-            id: uuidstr
-                .as_ref()
-                .and_then(|s| Uuid::parse_str(s).ok())
-                .ok_or(MyError::Parse("Message id not present"))?,
-            moderator: tags.get_parsed("mod"),
+            emote_only: flag("emote-only"),
+            emotes: emotes.map(|s| parse_list(&s, '/')).transpose()?,
+            first_msg: flag("first-msg"),
+            flags: flags.map(|s| parse_list(&s, ',')).transpose()?,
+            id: parse_uuid(uuidstr)?,
+            moderator: flag("mod"),
+            reply: Reply::from_tags(&tags)?,
+            returning_chatter: flag("returning-chatter"),
             room_id: tags
                 .get_parsed("room-id")
                 .ok_or(MyError::Parse("room id not present"))?,
-            tmi_sent_ts: timestr
-                .ok_or(MyError::Parse("Timestamp not present"))?
-                .parse::<u64>()
-                .map_err(|_| MyError::Parse("timestamp wasn't a u64"))
-                .map(|v| DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_millis(v)))?,
+            subscriber: flag("subscriber"),
+            tmi_sent_ts: parse_timestamp(timestr)?,
+            turbo: flag("turbo"),
             user_id: tags
                 .get_parsed("user-id")
                 .ok_or(MyError::Parse("User id not present"))?,
+            user_type: tags.get_parsed("user-type"),
+            vip: flag("vip"),
+            other,
         })
     }
 }
 
+///The message a reply is replying to, and the message that started the thread
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Reply {
+    pub parent_msg_id: Uuid,
+    pub parent_user_id: Option<String>,
+    pub parent_user_login: Option<String>,
+    pub parent_display_name: Option<String>,
+    pub parent_msg_body: Option<String>,
+    pub thread_parent_msg_id: Option<Uuid>,
+    pub thread_parent_user_login: Option<String>,
+}
+
+impl Reply {
+    fn from_tags(tags: &Tags) -> Result<Option<Box<Reply>>, MyError> {
+        let parent_msg_id: Option<String> = tags.get_parsed("reply-parent-msg-id");
+        let parent_msg_id = match parent_msg_id {
+            Some(id) => Uuid::parse_str(&id).map_err(|_| MyError::Parse("reply parent id"))?,
+            None => return Ok(None),
+        };
+        let thread_parent_msg_id: Option<String> = tags.get_parsed("reply-thread-parent-msg-id");
+        let thread_parent_msg_id = thread_parent_msg_id
+            .map(|id| Uuid::parse_str(&id).map_err(|_| MyError::Parse("reply thread id")))
+            .transpose()?;
+        let unescaped = |key: &str| tags.get(key).map(|v| unescape_tag(v));
+        Ok(Some(Box::new(Reply {
+            parent_msg_id,
+            parent_user_id: tags.get_parsed("reply-parent-user-id"),
+            parent_user_login: tags.get_parsed("reply-parent-user-login"),
+            parent_display_name: unescaped("reply-parent-display-name"),
+            parent_msg_body: unescaped("reply-parent-msg-body"),
+            thread_parent_msg_id,
+            thread_parent_user_login: tags.get_parsed("reply-thread-parent-user-login"),
+        })))
+    }
+}
+
+///Part of a message AutoMod flagged, eg - 0-4:P.6/S.3. The range is inclusive and the categories
+///are a letter and a level, eg - P.6 is profanity level 6. Flags for things like links have no
+///categories.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct AutoModFlag {
+    pub start: usize,
+    pub end: usize,
+    pub categories: Vec<String>,
+}
+
+impl FromStr for AutoModFlag {
+    type Err = MyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, categories) = s.split_once(':').ok_or(MyError::Parse("flag"))?;
+        let (start, end) = range.split_once('-').ok_or(MyError::Parse("flag range"))?;
+        Ok(AutoModFlag {
+            start: start.parse().map_err(|_| MyError::Parse("flag range"))?,
+            end: end.parse().map_err(|_| MyError::Parse("flag range"))?,
+            categories: categories
+                .split('/')
+                .filter(|c| !c.is_empty())
+                .map(str::to_string)
+                .collect(),
+        })
+    }
+}
+
+impl fmt::Display for AutoModFlag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}:{}",
+            self.start,
+            self.end,
+            self.categories.join("/")
+        )
+    }
+}
+
+impl TwitchTags {
+    ///Months subscribed to the channel, or None for non subscribers
+    pub fn subscription_months(&self) -> Option<i32> {
+        subscription_months(self.badge_info.as_deref()?)
+    }
+}
+
+///Founders are subscribers too and their badge info counts months the same way
+pub fn subscription_months(badge_info: &[Badge]) -> Option<i32> {
+    badge_info
+        .iter()
+        .find(|b| b.name == "subscriber" || b.name == "founder")
+        .and_then(|b| b.version.parse().ok())
+}
+
+///One of the badges shown next to a user's name, eg - subscriber/12
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Badge {
+    pub name: String,
+    pub version: String,
+}
+
+impl FromStr for Badge {
+    type Err = MyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, version) = s.split_once('/').ok_or(MyError::Parse("badge"))?;
+        Ok(Badge {
+            name: name.to_string(),
+            version: version.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Badge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.name, self.version)
+    }
+}
+
+///Every place one emote is used in a message
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct EmoteUsage {
+    pub emote_id: String,
+    pub ranges: Vec<EmoteRange>,
+}
+
+///Inclusive start and end of an emote in the message. Twitch counts in unicode code points, not
+///bytes or utf-16 units.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+pub struct EmoteRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl EmoteRange {
+    ///The emote's name, or None if the range isn't in the message
+    pub fn text<'a>(&self, message: &'a str) -> Option<&'a str> {
+        if self.start > self.end {
+            return None;
+        }
+        let mut offsets = message
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(message.len()));
+        let start = offsets.nth(self.start)?;
+        let end = offsets.nth(self.end - self.start)?;
+        Some(&message[start..end])
+    }
+}
+
+impl FromStr for EmoteUsage {
+    type Err = MyError;
+
+    ///eg - 25:0-4,12-16
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (emote_id, ranges) = s.split_once(':').ok_or(MyError::Parse("emote"))?;
+        let ranges = ranges
+            .split(',')
+            .map(|r| {
+                let (start, end) = r.split_once('-')?;
+                Some(EmoteRange {
+                    start: start.parse().ok()?,
+                    end: end.parse().ok()?,
+                })
+            })
+            .collect::<Option<Vec<EmoteRange>>>()
+            .ok_or(MyError::Parse("emote range"))?;
+        Ok(EmoteUsage {
+            emote_id: emote_id.to_string(),
+            ranges,
+        })
+    }
+}
+
+impl fmt::Display for EmoteUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.emote_id)?;
+        for (i, r) in self.ranges.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}-{}", r.start, r.end)?;
+        }
+        Ok(())
+    }
+}
+
+///badge-info, badges, emotes and flags tags are lists, empty when the user has none
+pub(crate) fn parse_list<T: FromStr<Err = MyError>>(
+    s: &str,
+    separator: char,
+) -> Result<Vec<T>, MyError> {
+    s.split(separator)
+        .filter(|s| !s.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub struct TwitchMessage {
     pub tags: TwitchTags,
     pub channel: String,
+    ///Without the ACTION wrapper if it's a /me message
     pub message: String,
+    ///Sent with /me
+    #[serde(default)]
+    pub action: bool,
     pub raw: String,
 }
 
-impl TryFrom<Arc<Privmsg<'_>>> for TwitchMessage {
+impl TryFrom<&Raw<'_>> for TwitchMessage {
     type Error = MyError;
 
     //TODO can get a lot of stuff from message directly may not need tags as much
-    fn try_from(msg: Arc<Privmsg>) -> Result<Self, Self::Error> {
-        let raw_msg: String = format!("{:?}", msg.clone());
+    fn try_from(raw: &Raw) -> Result<Self, Self::Error> {
+        let msg = Privmsg::parse(raw).map_err(|_| MyError::Parse("PRIVMSG"))?;
+        let (action, text) = strip_action(&msg.data);
+        let mut tags = TwitchTags::try_from(msg.tags.clone())?;
+        //an emote that doesn't fit the message is dropped rather than the whole message
+        if let Some(emotes) = &mut tags.emotes {
+            for emote in emotes.iter_mut() {
+                emote.ranges.retain(|r| r.text(text).is_some());
+            }
+            emotes.retain(|e| !e.ranges.is_empty());
+        }
         Ok(TwitchMessage {
-            tags: TwitchTags::try_from(msg.tags.clone())?,
+            tags,
+            channel: msg.channel.to_string(),
+            message: text.to_string(),
+            action,
+            raw: raw.raw.to_string(),
+        })
+    }
+}
+
+///Messages sent with /me are a CTCP ACTION, eg - \x01ACTION waves\x01. Twitch counts emote
+///positions from the text inside.
+fn strip_action(data: &str) -> (bool, &str) {
+    match data.strip_prefix("\u{1}ACTION ") {
+        Some(text) => (true, text.strip_suffix('\u{1}').unwrap_or(text)),
+        None => (false, data),
+    }
+}
+
+impl TwitchMessage {
+    ///The name of every emote used, once for each time it's used, in no particular order
+    pub fn emote_names(&self) -> Vec<&str> {
+        self.tags
+            .emotes
+            .iter()
+            .flatten()
+            .flat_map(|e| &e.ranges)
+            .filter_map(|r| r.text(&self.message))
+            .collect()
+    }
+}
+
+///USERNOTICE: subs, resubs, gift subs, raids and other announcements. Which `params` are
+///present depends on `msg_id`.
+///https://dev.twitch.tv/docs/irc/tags/#usernotice-twitch-tags
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct TwitchUserNotice {
+    pub id: Uuid,
+    pub channel: String,
+    pub room_id: i32,
+    ///eg - sub, resub, subgift, raid
+    pub msg_id: String,
+    pub user_id: Option<String>,
+    pub login: Option<String>,
+    pub display_name: Option<String>,
+    pub system_msg: Option<String>,
+    ///Message the user attached, eg - to a resub
+    pub message: Option<String>,
+    ///msg-param-* tags with the prefix removed
+    pub params: BTreeMap<String, String>,
+    pub tmi_sent_ts: DateTime<Utc>,
+    pub raw: String,
+}
+
+impl TryFrom<&Raw<'_>> for TwitchUserNotice {
+    type Error = MyError;
+
+    fn try_from(raw: &Raw) -> Result<Self, Self::Error> {
+        let msg = UserNotice::parse(raw).map_err(|_| MyError::Parse("USERNOTICE"))?;
+        let tags = &msg.tags;
+        let uuidstr: Option<String> = tags.get_parsed("id");
+        let params = tags
+            .iter()
+            .filter_map(|(k, v)| {
+                k.strip_prefix("msg-param-")
+                    .map(|k| (k.to_string(), unescape_tag(v)))
+            })
+            .collect();
+        Ok(TwitchUserNotice {
+            id: parse_uuid(uuidstr)?,
+            channel: msg.channel.to_string(),
+            room_id: tags
+                .get_parsed("room-id")
+                .ok_or(MyError::Parse("room id not present"))?,
+            msg_id: tags
+                .get_parsed("msg-id")
+                .ok_or(MyError::Parse("msg-id not present"))?,
+            user_id: tags.get_parsed("user-id"),
+            login: tags.get_parsed("login"),
+            display_name: tags.get_parsed("display-name"),
+            system_msg: msg.system_msg(),
+            message: msg.message.as_ref().map(|m| m.to_string()),
+            params,
+            tmi_sent_ts: parse_timestamp(tags.get_parsed("tmi-sent-ts"))?,
+            raw: raw.raw.to_string(),
+        })
+    }
+}
+
+///CLEARCHAT: a user was timed out or banned, or the whole chat was cleared if there's no user
+///https://dev.twitch.tv/docs/irc/tags/#clearchat-twitch-tags
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct TwitchClearChat {
+    pub channel: String,
+    pub room_id: i32,
+    pub target_login: Option<String>,
+    pub target_user_id: Option<String>,
+    ///Seconds. None for a permanent ban.
+    pub ban_duration: Option<i32>,
+    pub tmi_sent_ts: DateTime<Utc>,
+    pub raw: String,
+}
+
+impl TryFrom<&Raw<'_>> for TwitchClearChat {
+    type Error = MyError;
+
+    fn try_from(raw: &Raw) -> Result<Self, Self::Error> {
+        let msg = ClearChat::parse(raw).map_err(|_| MyError::Parse("CLEARCHAT"))?;
+        let tags = &msg.tags;
+        Ok(TwitchClearChat {
             channel: msg.channel.to_string(),
-            message: msg.data.to_string(),
-            raw: raw_msg,
+            room_id: tags
+                .get_parsed("room-id")
+                .ok_or(MyError::Parse("room id not present"))?,
+            target_login: msg.name.as_ref().map(|n| n.to_string()),
+            target_user_id: tags.get_parsed("target-user-id"),
+            ban_duration: tags.get_parsed("ban-duration"),
+            tmi_sent_ts: parse_timestamp(tags.get_parsed("tmi-sent-ts"))?,
+            raw: raw.raw.to_string(),
         })
     }
 }
+
+///CLEARMSG: a single message was deleted
+///https://dev.twitch.tv/docs/irc/tags/#clearmsg-twitch-tags
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct TwitchClearMsg {
+    pub channel: String,
+    pub login: Option<String>,
+    ///Id of the deleted message
+    pub target_msg_id: Uuid,
+    pub message: Option<String>,
+    pub tmi_sent_ts: DateTime<Utc>,
+    pub raw: String,
+}
+
+impl TryFrom<&Raw<'_>> for TwitchClearMsg {
+    type Error = MyError;
+
+    fn try_from(raw: &Raw) -> Result<Self, Self::Error> {
+        let msg = ClearMsg::parse(raw).map_err(|_| MyError::Parse("CLEARMSG"))?;
+        let tags = &msg.tags;
+        let uuidstr: Option<String> = tags.get_parsed("target-msg-id");
+        Ok(TwitchClearMsg {
+            channel: msg.channel.to_string(),
+            login: tags.get_parsed("login"),
+            target_msg_id: parse_uuid(uuidstr)?,
+            message: msg.message.as_ref().map(|m| m.to_string()),
+            tmi_sent_ts: parse_timestamp(tags.get_parsed("tmi-sent-ts"))?,
+            raw: raw.raw.to_string(),
+        })
+    }
+}
+
+///ROOMSTATE: chat settings for a channel. Sent in full on join, then with only the changed
+///setting, so unset fields are unchanged rather than off.
+///https://dev.twitch.tv/docs/irc/tags/#roomstate-twitch-tags
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct TwitchRoomState {
+    pub channel: String,
+    pub room_id: i32,
+    pub emote_only: Option<bool>,
+    ///Minutes an account has to have followed for. -1 is off.
+    pub followers_only: Option<i32>,
+    pub r9k: Option<bool>,
+    ///Seconds between messages. 0 is off.
+    pub slow: Option<i32>,
+    pub subs_only: Option<bool>,
+    ///ROOMSTATE has no tmi-sent-ts so this is when it was received
+    pub received_at: DateTime<Utc>,
+    pub raw: String,
+}
+
+impl TryFrom<&Raw<'_>> for TwitchRoomState {
+    type Error = MyError;
+
+    fn try_from(raw: &Raw) -> Result<Self, Self::Error> {
+        let msg = RoomState::parse(raw).map_err(|_| MyError::Parse("ROOMSTATE"))?;
+        let tags = &msg.tags;
+        let flag = |key: &str| tags.get_parsed::<_, u8>(key).map(|v| v != 0);
+        Ok(TwitchRoomState {
+            channel: msg.channel.to_string(),
+            room_id: tags
+                .get_parsed("room-id")
+                .ok_or(MyError::Parse("room id not present"))?,
+            emote_only: flag("emote-only"),
+            followers_only: tags.get_parsed("followers-only"),
+            r9k: flag("r9k"),
+            slow: tags.get_parsed("slow"),
+            subs_only: flag("subs-only"),
+            received_at: Utc::now(),
+            raw: raw.raw.to_string(),
+        })
+    }
+}
+
+fn parse_uuid(s: Option<String>) -> Result<Uuid, MyError> {
+    s.as_ref()
+        .and_then(|s| Uuid::parse_str(s).ok())
+        .ok_or(MyError::Parse("Message id not present"))
+}
+
+fn parse_timestamp(s: Option<String>) -> Result<DateTime<Utc>, MyError> {
+    s.ok_or(MyError::Parse("Timestamp not present"))?
+        .parse::<u64>()
+        .map_err(|_| MyError::Parse("timestamp wasn't a u64"))
+        .map(|v| DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_millis(v)))
+}
+
+//https://ircv3.net/specs/extensions/message-tags#escaping-values
+fn unescape_tag(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => out.push(';'),
+            Some('s') => out.push(' '),
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+pub enum PresenceKind {
+    Join,
+    Part,
+}
+
+impl PresenceKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PresenceKind::Join => "join",
+            PresenceKind::Part => "part",
+        }
+    }
+}
+
+///A viewer joining or leaving a channel, from the membership capability. Twitch sends these in
+///batches every few seconds and only for channels with under 1000 chatters, so they're good for
+///estimating chatter counts but the times are approximate.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Presence {
+    pub channel: String,
+    pub login: String,
+    pub kind: PresenceKind,
+    pub received_at: DateTime<Utc>,
+}
+
+///A period when a channel wasn't being listened to, eg - because its connection dropped. Messages
+///sent in the channel during this time are missing.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Outage {
+    pub channel: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub reason: String,
+}
+
+///Everything the collector passes on to a sink
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum Record {
+    Message(TwitchMessage),
+    UserNotice(TwitchUserNotice),
+    ClearChat(TwitchClearChat),
+    ClearMsg(TwitchClearMsg),
+    RoomState(TwitchRoomState),
+    Presence(Presence),
+    Outage(Outage),
+}
+
+impl Record {
+    ///The record for a line from IRC, or None for commands that aren't recorded. The line is kept
+    ///as it was received so it can be parsed again.
+    pub fn from_raw(raw: &Raw) -> Result<Option<Record>, MyError> {
+        Ok(Some(match raw.command.as_ref() {
+            "PRIVMSG" => Record::Message(TwitchMessage::try_from(raw)?),
+            "USERNOTICE" => Record::UserNotice(TwitchUserNotice::try_from(raw)?),
+            "CLEARCHAT" => Record::ClearChat(TwitchClearChat::try_from(raw)?),
+            "CLEARMSG" => Record::ClearMsg(TwitchClearMsg::try_from(raw)?),
+            "ROOMSTATE" => Record::RoomState(TwitchRoomState::try_from(raw)?),
+            _ => return Ok(None),
+        }))
+    }
+
+    ///The channel the record is from. Depending on the record this may or may not start with #.
+    pub fn channel(&self) -> &str {
+        match self {
+            Record::Message(m) => &m.channel,
+            Record::UserNotice(n) => &n.channel,
+            Record::ClearChat(c) => &c.channel,
+            Record::ClearMsg(c) => &c.channel,
+            Record::RoomState(r) => &r.channel,
+            Record::Presence(p) => &p.channel,
+            Record::Outage(o) => &o.channel,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod samples {
+    use super::*;
+    use chrono::TimeZone;
+
+    macro_rules! parse {
+        ($raw:expr) => {
+            twitchchat::decode_one($raw).unwrap().1
+        };
+    }
+
+    pub(crate) const PRIVMSG: &str = "@badge-info=subscriber/8;badges=subscriber/6,premium/1;color=#0000FF;display-name=Ronni;emotes=25:0-4,12-16/1902:6-10;id=b34ccfc7-4977-403a-8a94-33c6bac34fb8;mod=0;room-id=1337;subscriber=1;tmi-sent-ts=1507246572675;turbo=1;user-id=1337;user-type=global_mod :ronni!ronni@ronni.tmi.twitch.tv PRIVMSG #ronni :Kappa Keepo Kappa\r\n";
+    pub(crate) const REPLY: &str = "@badge-info=;badges=vip/1;client-nonce=4f3c1b2a;color=;display-name=Viewer;emotes=;first-msg=1;flags=0-4:P.6/S.3,13-16:;id=0b6d5f3e-1c1a-4a47-8a66-7d1f0c9b4c11;mod=0;pinned-chat-paid-amount=500;reply-parent-display-name=Ronni;reply-parent-msg-body=Kappa\\sKeepo\\sKappa;reply-parent-msg-id=b34ccfc7-4977-403a-8a94-33c6bac34fb8;reply-parent-user-id=1337;reply-parent-user-login=ronni;reply-thread-parent-msg-id=b34ccfc7-4977-403a-8a94-33c6bac34fb8;reply-thread-parent-user-login=ronni;returning-chatter=0;room-id=1337;subscriber=0;tmi-sent-ts=1507246600000;turbo=0;user-id=42;user-type=;vip=1 :viewer!viewer@viewer.tmi.twitch.tv PRIVMSG #ronni :@ronni heck yes\r\n";
+    pub(crate) const USERNOTICE: &str = "@badge-info=;badges=staff/1,broadcaster/1;color=#008000;display-name=ronni;emotes=;id=db25007f-7a18-43eb-9379-80131e44d633;login=ronni;mod=0;msg-id=resub;msg-param-cumulative-months=6;msg-param-streak-months=2;msg-param-should-share-streak=1;msg-param-sub-plan=Prime;msg-param-sub-plan-name=Prime\\sSub;room-id=1337;subscriber=1;system-msg=ronni\\shas\\ssubscribed\\sfor\\s6\\smonths!;tmi-sent-ts=1507246572675;turbo=1;user-id=1337;user-type=staff :tmi.twitch.tv USERNOTICE #dallas :Great stream -- keep it up!\r\n";
+    ///A /me message, positions are counted from the text inside the ACTION
+    pub(crate) const ACTION: &str = "@badges=;color=;display-name=Ronni;emotes=25:6-10;id=7f1e2d3c-4b5a-4968-8776-a5b4c3d2e1f0;mod=0;room-id=1337;tmi-sent-ts=1507246573000;user-id=1337 :ronni!ronni@ronni.tmi.twitch.tv PRIVMSG #ronni :\u{1}ACTION waves Kappa\u{1}\r\n";
+    pub(crate) const CLEARCHAT: &str = "@ban-duration=350;room-id=12345678;target-user-id=87654321;tmi-sent-ts=1642715756806 :tmi.twitch.tv CLEARCHAT #dallas :ronni\r\n";
+    pub(crate) const CLEARMSG: &str = "@login=foo;room-id=;target-msg-id=94e6c7ff-bf98-4faa-af5d-7ad633a158a9;tmi-sent-ts=1642720582342 :tmi.twitch.tv CLEARMSG #bar :what a great day\r\n";
+    pub(crate) const ROOMSTATE: &str = "@emote-only=0;followers-only=-1;r9k=0;room-id=12345678;slow=0;subs-only=0 :tmi.twitch.tv ROOMSTATE #bar\r\n";
+
+    pub(crate) fn message() -> TwitchMessage {
+        TwitchMessage::try_from(&parse!(PRIVMSG)).unwrap()
+    }
+
+    ///A first message from a vip, replying to `message`
+    pub(crate) fn reply() -> TwitchMessage {
+        TwitchMessage::try_from(&parse!(REPLY)).unwrap()
+    }
+
+    ///A /me message with an emote
+    pub(crate) fn action() -> TwitchMessage {
+        TwitchMessage::try_from(&parse!(ACTION)).unwrap()
+    }
+
+    ///One of each kind of record
+    pub(crate) fn records() -> Vec<Record> {
+        let at = Utc.timestamp_millis_opt(1507246572675).unwrap();
+        vec![
+            Record::Message(message()),
+            Record::UserNotice(TwitchUserNotice::try_from(&parse!(USERNOTICE)).unwrap()),
+            Record::ClearChat(TwitchClearChat::try_from(&parse!(CLEARCHAT)).unwrap()),
+            Record::ClearMsg(TwitchClearMsg::try_from(&parse!(CLEARMSG)).unwrap()),
+            Record::RoomState(TwitchRoomState::try_from(&parse!(ROOMSTATE)).unwrap()),
+            Record::Presence(Presence {
+                channel: "#dallas".to_string(),
+                login: "viewer".to_string(),
+                kind: PresenceKind::Join,
+                received_at: at,
+            }),
+            Record::Outage(Outage {
+                channel: "#dallas".to_string(),
+                start: at,
+                end: at + chrono::Duration::seconds(5),
+                reason: "eof".to_string(),
+            }),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! parse {
+        ($raw:expr) => {
+            twitchchat::decode_one($raw).unwrap().1
+        };
+    }
+
+    #[test]
+    fn test_badges_and_emotes() {
+        let tags = samples::message().tags;
+        let badges = tags.badges.unwrap();
+        assert_eq!(
+            badges[0],
+            Badge {
+                name: "subscriber".to_string(),
+                version: "6".to_string()
+            }
+        );
+        assert_eq!(badges[1].to_string(), "premium/1");
+        let emotes = tags.emotes.unwrap();
+        assert_eq!(emotes.len(), 2);
+        assert_eq!(emotes[0].emote_id, "25");
+        assert_eq!(
+            emotes[0].ranges,
+            vec![
+                EmoteRange { start: 0, end: 4 },
+                EmoteRange { start: 12, end: 16 }
+            ]
+        );
+        assert_eq!(emotes[1].to_string(), "1902:6-10");
+
+        assert!(parse_list::<Badge>("", ',').unwrap().is_empty());
+        assert!("25:0-".parse::<EmoteUsage>().is_err());
+        assert!("subscriber".parse::<Badge>().is_err());
+    }
+
+    #[test]
+    fn test_privmsg_tags() {
+        let tags = samples::message().tags;
+        assert_eq!(tags.moderator, Some(false));
+        assert_eq!(tags.subscriber, Some(true));
+        assert_eq!(tags.turbo, Some(true));
+        assert_eq!(tags.user_type.as_deref(), Some("global_mod"));
+        assert_eq!(tags.vip, None);
+        assert_eq!(tags.reply, None);
+        assert!(tags.other.is_empty());
+
+        let tags = samples::reply().tags;
+        assert_eq!(tags.client_nonce.as_deref(), Some("4f3c1b2a"));
+        assert_eq!(tags.first_msg, Some(true));
+        assert_eq!(tags.returning_chatter, Some(false));
+        assert_eq!(tags.vip, Some(true));
+        assert_eq!(tags.user_type.as_deref(), Some(""));
+        let flags = tags.flags.unwrap();
+        assert_eq!(flags[0].categories, vec!["P.6", "S.3"]);
+        assert!(flags[1].categories.is_empty());
+        assert_eq!(flags[1].to_string(), "13-16:");
+        let reply = tags.reply.unwrap();
+        assert_eq!(reply.parent_msg_id, samples::message().tags.id);
+        assert_eq!(reply.parent_msg_body.as_deref(), Some("Kappa Keepo Kappa"));
+        assert_eq!(reply.thread_parent_user_login.as_deref(), Some("ronni"));
+        assert_eq!(tags.other.len(), 1);
+        assert_eq!(tags.other["pinned-chat-paid-amount"], "500");
+    }
+
+    #[test]
+    fn test_record_from_raw() {
+        let record = Record::from_raw(&parse!(samples::PRIVMSG))
+            .unwrap()
+            .unwrap();
+        let message = match record {
+            Record::Message(m) => m,
+            r => panic!("expected a message, got {:?}", r),
+        };
+        //the line is kept as it was sent so it can be parsed again
+        assert_eq!(message.raw, samples::PRIVMSG);
+        let again = TwitchMessage::try_from(&parse!(&message.raw)).unwrap();
+        assert_eq!(again, message);
+
+        let ping = "PING :tmi.twitch.tv\r\n";
+        assert!(Record::from_raw(&parse!(ping)).unwrap().is_none());
+        let broken = samples::PRIVMSG.replace("room-id=1337", "room-id=x");
+        assert!(Record::from_raw(&parse!(&broken)).is_err());
+    }
+
+    #[test]
+    fn test_subscription_months() {
+        let tags = samples::message().tags;
+        assert_eq!(
+            tags.badge_info.as_ref().unwrap()[0].to_string(),
+            "subscriber/8"
+        );
+        assert_eq!(tags.subscription_months(), Some(8));
+
+        let months = |s: &str| subscription_months(&parse_list::<Badge>(s, ',').unwrap());
+        assert_eq!(months("founder/14"), Some(14));
+        assert_eq!(months("predictions/blue-1,subscriber/3"), Some(3));
+        assert_eq!(months("predictions/blue-1"), None);
+        assert_eq!(months(""), None);
+    }
+
+    #[test]
+    fn test_emote_names() {
+        let message = samples::message();
+        let mut names = message.emote_names();
+        names.sort_unstable();
+        assert_eq!(names, vec!["Kappa", "Kappa", "Keepo"]);
+
+        //positions are in code points so the emoji counts as one
+        let raw = "@badges=;color=;display-name=Ronni;emotes=25:2-6;id=b34ccfc7-4977-403a-8a94-33c6bac34fb8;mod=0;room-id=1337;tmi-sent-ts=1507246572675;user-id=1337 :ronni!ronni@ronni.tmi.twitch.tv PRIVMSG #ronni :\u{1F600} Kappa \u{e9}\r\n";
+        let message = TwitchMessage::try_from(&parse!(raw)).unwrap();
+        assert_eq!(message.emote_names(), vec!["Kappa"]);
+
+        //only the emote that doesn't fit is dropped
+        let raw = raw.replace("25:2-6", "25:2-6/1902:2-20");
+        let message = TwitchMessage::try_from(&parse!(&raw)).unwrap();
+        assert_eq!(message.emote_names(), vec!["Kappa"]);
+        assert_eq!(message.tags.emotes.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_action_message() {
+        let message = samples::action();
+        assert!(message.action);
+        assert_eq!(message.message, "waves Kappa");
+        assert_eq!(message.emote_names(), vec!["Kappa"]);
+        assert!(!samples::message().action);
+    }
+
+    #[test]
+    fn test_emote_range_text() {
+        let range = |start, end| EmoteRange { start, end };
+        assert_eq!(range(0, 4).text("Kappa"), Some("Kappa"));
+        assert_eq!(range(1, 1).text("\u{e9}\u{e9}"), Some("\u{e9}"));
+        assert_eq!(range(0, 5).text("Kappa"), None);
+        assert_eq!(range(3, 2).text("Kappa"), None);
+    }
+
+    #[test]
+    fn test_resub_user_notice() {
+        let raw = samples::USERNOTICE;
+        let notice = TwitchUserNotice::try_from(&parse!(raw)).unwrap();
+        assert_eq!(notice.msg_id, "resub");
+        assert_eq!(notice.channel, "#dallas");
+        assert_eq!(notice.room_id, 1337);
+        assert_eq!(notice.login.as_deref(), Some("ronni"));
+        assert_eq!(
+            notice.system_msg.as_deref(),
+            Some("ronni has subscribed for 6 months!")
+        );
+        assert_eq!(
+            notice.message.as_deref(),
+            Some("Great stream -- keep it up!")
+        );
+        assert_eq!(notice.params.len(), 5);
+        assert_eq!(notice.params["cumulative-months"], "6");
+        assert_eq!(notice.params["sub-plan-name"], "Prime Sub");
+        assert_eq!(notice.tmi_sent_ts.timestamp_millis(), 1507246572675);
+    }
+
+    #[test]
+    fn test_raid_user_notice() {
+        let raw = "@badge-info=;badges=;color=;display-name=TestChannel;emotes=;id=3d830f12-795c-447d-af3c-ea05e40fbddb;login=testchannel;mod=0;msg-id=raid;msg-param-displayName=TestChannel;msg-param-login=testchannel;msg-param-viewerCount=15;room-id=56379257;subscriber=0;system-msg=15\\sraiders\\sfrom\\sTestChannel\\shave\\sjoined\\n!;tmi-sent-ts=1507246572675;turbo=0;user-id=123456;user-type= :tmi.twitch.tv USERNOTICE #othertestchannel\r\n";
+        let notice = TwitchUserNotice::try_from(&parse!(raw)).unwrap();
+        assert_eq!(notice.msg_id, "raid");
+        assert_eq!(notice.message, None);
+        assert_eq!(notice.params["viewerCount"], "15");
+    }
+
+    #[test]
+    fn test_clear_chat() {
+        let raw = samples::CLEARCHAT;
+        let clear = TwitchClearChat::try_from(&parse!(raw)).unwrap();
+        assert_eq!(clear.target_login.as_deref(), Some("ronni"));
+        assert_eq!(clear.target_user_id.as_deref(), Some("87654321"));
+        assert_eq!(clear.ban_duration, Some(350));
+
+        let raw =
+            "@room-id=12345678;tmi-sent-ts=1642715695392 :tmi.twitch.tv CLEARCHAT #dallas\r\n";
+        let clear = TwitchClearChat::try_from(&parse!(raw)).unwrap();
+        assert_eq!(clear.target_login, None);
+        assert_eq!(clear.ban_duration, None);
+    }
+
+    #[test]
+    fn test_clear_msg() {
+        let raw = samples::CLEARMSG;
+        let clear = TwitchClearMsg::try_from(&parse!(raw)).unwrap();
+        assert_eq!(clear.login.as_deref(), Some("foo"));
+        assert_eq!(
+            clear.target_msg_id,
+            Uuid::parse_str("94e6c7ff-bf98-4faa-af5d-7ad633a158a9").unwrap()
+        );
+        assert_eq!(clear.message.as_deref(), Some("what a great day"));
+    }
+
+    #[test]
+    fn test_room_state() {
+        let raw = samples::ROOMSTATE;
+        let state = TwitchRoomState::try_from(&parse!(raw)).unwrap();
+        assert_eq!(state.emote_only, Some(false));
+        assert_eq!(state.followers_only, Some(-1));
+        assert_eq!(state.slow, Some(0));
+
+        let raw = "@room-id=12345678;slow=10 :tmi.twitch.tv ROOMSTATE #bar\r\n";
+        let state = TwitchRoomState::try_from(&parse!(raw)).unwrap();
+        assert_eq!(state.slow, Some(10));
+        assert_eq!(state.subs_only, None);
+    }
+
+    #[test]
+    fn test_unescape_tag() {
+        assert_eq!(unescape_tag("a\\sb\\:c\\\\d\\n"), "a b;c\\d\n");
+    }
+}